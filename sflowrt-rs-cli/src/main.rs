@@ -1,22 +1,96 @@
 //! `reedline_repl_rs`Minimal example
 
-use reedline_repl_rs::clap::{Arg, ArgMatches, Command};
+use reedline_repl_rs::clap::{Arg, ArgAction, ArgMatches, Command};
 use reedline_repl_rs::{Repl, Result};
 use sflowrt_rs_flow::key::{
-    key_parser::{finish_nom_parse, parse_key_definition},
+    key_error::KeySyntaxError,
+    key_lint::{Linter, Severity},
+    key_parser::parse_key_definition_recovering,
+    key_signature::check_key_definition,
+    key_suggest::find_unknown_names,
     KeyDefinition,
 };
 
 /// Parse an sFlow-RT Flow key definition.
 fn parse_key<T>(args: ArgMatches, _context: &mut T) -> anyhow::Result<Option<String>> {
     let input: String = args.get_one::<String>("key-definition").unwrap().to_owned();
-    let (leftover, definition): (String, KeyDefinition) =
-        finish_nom_parse(parse_key_definition(&input))?;
+    let (definition, syntax_errors): (KeyDefinition, _) = parse_key_definition_recovering(&input);
+    if !syntax_errors.is_empty() {
+        let rendered: Vec<String> = syntax_errors.iter().map(|e| e.render()).collect();
+        anyhow::bail!(
+            "Parsing failed.\n\n{}\n\nParsed: {definition:?}",
+            rendered.join("\n\n")
+        );
+    }
+    let errors: Vec<String> = check_key_definition(&definition)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    anyhow::ensure!(
+        errors.is_empty(),
+        format!(
+            "Invalid key definition.\n\n{}\n\nParsed: {definition:?}",
+            errors.join("\n")
+        )
+    );
+    let mut output = format!("{definition:?}");
+    for unknown in find_unknown_names(&definition) {
+        output.push_str(&format!("\nwarning: {unknown}"));
+    }
+    Ok(Some(output))
+}
+
+/// Show the structural changes between two sFlow-RT Flow key definitions.
+fn diff_key<T>(args: ArgMatches, _context: &mut T) -> anyhow::Result<Option<String>> {
+    let [old, new] = ["old-key-definition", "new-key-definition"].map(|arg| {
+        args.get_one::<String>(arg)
+            .unwrap()
+            .parse::<KeyDefinition>()
+            .map_err(|e: KeySyntaxError| anyhow::anyhow!("Parsing failed.\n\n{}", e.render()))
+    });
+    let diff = old?.diff(&new?);
+    if diff.is_empty() {
+        return Ok(Some("no changes".to_string()));
+    }
+    Ok(Some(diff.to_string()))
+}
+
+/// Lint an sFlow-RT Flow key definition, with the built-in lint rules at their default
+/// severities unless overridden with `--allow`, `--warn` or `--deny`.
+fn lint_key<T>(args: ArgMatches, _context: &mut T) -> anyhow::Result<Option<String>> {
+    let input: String = args.get_one::<String>("key-definition").unwrap().to_owned();
+    let definition: KeyDefinition = input
+        .parse()
+        .map_err(|e: KeySyntaxError| anyhow::anyhow!("Parsing failed.\n\n{}", e.render()))?;
+    let mut linter = Linter::default();
+    for (flag, severity) in [
+        ("allow", Severity::Allow),
+        ("warn", Severity::Warning),
+        ("deny", Severity::Error),
+    ] {
+        for rule in args.get_many::<String>(flag).into_iter().flatten() {
+            linter = linter.with_severity(rule, severity)?;
+        }
+    }
+    let lints = linter.lint(&definition);
+    let rendered: Vec<String> = lints.iter().map(ToString::to_string).collect();
     anyhow::ensure!(
-        leftover.is_empty(),
-        format!("Parsing failed.\n\nRemaining input: {leftover}\n\nParsed: {definition:?}")
+        lints.iter().all(|lint| lint.severity < Severity::Error),
+        format!("Lint failed.\n\n{}", rendered.join("\n"))
     );
-    Ok(Some(format!("{definition:?}")))
+    if rendered.is_empty() {
+        return Ok(Some("no lints".to_string()));
+    }
+    Ok(Some(rendered.join("\n")))
+}
+
+/// List the lint rules `lint-key` runs.
+fn lint_rules<T>(_args: ArgMatches, _context: &mut T) -> anyhow::Result<Option<String>> {
+    let rules: Vec<String> = Linter::default()
+        .rules()
+        .map(|(rule, severity)| format!("{} ({severity}): {}", rule.name(), rule.description()))
+        .collect();
+    Ok(Some(rules.join("\n")))
 }
 
 fn main() -> Result<()> {
@@ -30,6 +104,30 @@ fn main() -> Result<()> {
                 .arg(Arg::new("key-definition").required(true))
                 .about("Parse an sFlow-RT Flow key definition."),
             parse_key,
+        )
+        .with_command(
+            Command::new("lint-key")
+                .arg(Arg::new("key-definition").required(true))
+                .args(["allow", "warn", "deny"].map(|flag| {
+                    Arg::new(flag)
+                        .long(flag)
+                        .value_name("rule")
+                        .action(ArgAction::Append)
+                        .help(format!("{flag} a lint rule; see `lint-rules`"))
+                }))
+                .about("Lint an sFlow-RT Flow key definition."),
+            lint_key,
+        )
+        .with_command(
+            Command::new("diff-key")
+                .arg(Arg::new("old-key-definition").required(true))
+                .arg(Arg::new("new-key-definition").required(true))
+                .about("Show the changes between two sFlow-RT Flow key definitions."),
+            diff_key,
+        )
+        .with_command(
+            Command::new("lint-rules").about("List the lint rules `lint-key` runs."),
+            lint_rules,
         );
     repl.run()
 }
@@ -0,0 +1,251 @@
+//! Procedural macros for the `sflowrt-rs-flow` crate.
+
+use std::net::IpAddr;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+use sflowrt_rs_flow::key::{
+    key_function::{CountryKeyFunction, CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction},
+    key_literal::{AddressLiteral, Literal},
+    key_parser::parse_key_definition_spanned,
+    key_signature::check_key_definition,
+    key_suggest::find_unknown_names,
+    key_type::check_key_definition_types,
+    KeyDefinition, KeyExpression, KeyFunction, KeyName,
+};
+
+/// Parse a key definition at compile time, expanding to the
+/// `sflowrt_rs_flow::key::KeyDefinition` it describes.
+///
+/// ```
+/// use sflowrt_rs_flow::key::KeyDefinition;
+/// use sflowrt_rs_flow_macros::key_def;
+///
+/// let definition = key_def!("ipsource,group:ipdestination:internal");
+/// let parsed: KeyDefinition = "ipsource,group:ipdestination:internal".parse().unwrap();
+/// assert_eq!(definition, parsed);
+/// ```
+///
+/// A syntax error, an unknown key or key function, or a call that doesn't match its
+/// function's signature or argument types fails the build:
+///
+/// ```compile_fail
+/// let definition = sflowrt_rs_flow_macros::key_def!("ipsorce,group:ipdestination:internal");
+/// ```
+///
+/// Key functions registered at runtime (via `register_key_function`) aren't known
+/// when the macro runs, so calls to them are rejected as unknown; parse definitions
+/// that use them at runtime instead.
+#[proc_macro]
+pub fn key_def(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    match expand_key_def(&literal.value()) {
+        Ok(tokens) => tokens.into(),
+        Err(message) => syn::Error::new(literal.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+/// Parse and check `input`, returning the expression constructing its key
+/// definition, or a description of everything wrong with it.
+fn expand_key_def(input: &str) -> Result<TokenStream2, String> {
+    let spanned = parse_key_definition_spanned(input)
+        .map_err(|error| format!("invalid key definition:\n{}", error.render()))?;
+    let definition = spanned.to_key_definition();
+    let problems: Vec<String> = check_key_definition(&definition)
+        .iter()
+        .map(ToString::to_string)
+        .chain(
+            find_unknown_names(&definition)
+                .iter()
+                .map(ToString::to_string),
+        )
+        .chain(
+            check_key_definition_types(&spanned)
+                .iter()
+                .map(ToString::to_string),
+        )
+        .collect();
+    if !problems.is_empty() {
+        return Err(format!("invalid key definition: {}", problems.join("; ")));
+    }
+    Ok(key_definition_tokens(&definition))
+}
+
+fn key_definition_tokens(definition: &KeyDefinition) -> TokenStream2 {
+    let keys = definition.iter().map(key_expression_tokens);
+    quote! {
+        ::sflowrt_rs_flow::key::KeyDefinition::new(::std::vec![#(#keys),*])
+    }
+}
+
+fn key_expression_tokens(expression: &KeyExpression) -> TokenStream2 {
+    match expression {
+        KeyExpression::KeyName(key_name) => {
+            let key_name = key_name_tokens(key_name);
+            quote! { ::sflowrt_rs_flow::key::KeyExpression::KeyName(#key_name) }
+        }
+        KeyExpression::KeyFunction(function) => {
+            let function = key_function_tokens(function);
+            quote! { ::sflowrt_rs_flow::key::KeyExpression::KeyFunction(#function) }
+        }
+        KeyExpression::Literal(literal) => {
+            let literal = literal_tokens(literal);
+            quote! { ::sflowrt_rs_flow::key::KeyExpression::Literal(#literal) }
+        }
+    }
+}
+
+fn key_name_tokens(key_name: &KeyName) -> TokenStream2 {
+    let identifier = key_name.to_string();
+    quote! { ::sflowrt_rs_flow::key::KeyName::from(#identifier) }
+}
+
+fn key_function_tokens(function: &KeyFunction) -> TokenStream2 {
+    match function {
+        KeyFunction::Group(GroupKeyFunction { key, group_names }) => {
+            let key = key_expression_tokens(key);
+            quote! {
+                ::sflowrt_rs_flow::key::KeyFunction::Group(
+                    ::sflowrt_rs_flow::key::key_function::GroupKeyFunction {
+                        key: ::std::boxed::Box::new(#key),
+                        group_names: ::std::vec![#(::std::string::String::from(#group_names)),*],
+                    }
+                )
+            }
+        }
+        KeyFunction::Country(CountryKeyFunction { arg }) => quote! {
+            ::sflowrt_rs_flow::key::KeyFunction::Country(
+                ::sflowrt_rs_flow::key::key_function::CountryKeyFunction {
+                    arg: ::std::string::String::from(#arg),
+                }
+            )
+        },
+        KeyFunction::Custom(CustomKeyFunction {
+            function_name,
+            args,
+        }) => {
+            let args = args.iter().map(key_expression_tokens);
+            quote! {
+                ::sflowrt_rs_flow::key::KeyFunction::Custom(
+                    ::sflowrt_rs_flow::key::key_function::CustomKeyFunction {
+                        function_name: ::std::string::String::from(#function_name),
+                        args: ::std::vec![#(#args),*],
+                    }
+                )
+            }
+        }
+        KeyFunction::Unknown(UnknownKeyFunction {
+            function_name,
+            args,
+        }) => {
+            let args = args.iter().map(key_expression_tokens);
+            quote! {
+                ::sflowrt_rs_flow::key::KeyFunction::Unknown(
+                    ::sflowrt_rs_flow::key::key_function::UnknownKeyFunction {
+                        function_name: ::std::string::String::from(#function_name),
+                        args: ::std::vec![#(#args),*],
+                    }
+                )
+            }
+        }
+    }
+}
+
+fn literal_tokens(literal: &Literal) -> TokenStream2 {
+    match literal {
+        Literal::Integer(value) => {
+            let value = integer_tokens(*value);
+            quote! { ::sflowrt_rs_flow::key::key_literal::Literal::Integer(#value) }
+        }
+        Literal::String(value) => quote! {
+            ::sflowrt_rs_flow::key::key_literal::Literal::String(::std::string::String::from(#value))
+        },
+        Literal::Address(address) => {
+            let address = address_tokens(address);
+            quote! { ::sflowrt_rs_flow::key::key_literal::Literal::Address(#address) }
+        }
+        Literal::Regex(pattern) => quote! {
+            ::sflowrt_rs_flow::key::key_literal::Literal::Regex(::std::string::String::from(#pattern))
+        },
+    }
+}
+
+/// An `i64` expression for `value`. A negative integer isn't a single token, and
+/// `i64::MIN` has no positive counterpart to negate.
+fn integer_tokens(value: i64) -> TokenStream2 {
+    if value == i64::MIN {
+        quote! { ::std::primitive::i64::MIN }
+    } else if value < 0 {
+        let magnitude = proc_macro2::Literal::i64_suffixed(-value);
+        quote! { -#magnitude }
+    } else {
+        let value = proc_macro2::Literal::i64_suffixed(value);
+        quote! { #value }
+    }
+}
+
+fn address_tokens(address: &AddressLiteral) -> TokenStream2 {
+    let ip = match address.address {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            quote! { ::std::net::IpAddr::V4(::std::net::Ipv4Addr::new(#(#octets),*)) }
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            quote! { ::std::net::IpAddr::V6(::std::net::Ipv6Addr::new(#(#segments),*)) }
+        }
+    };
+    let prefix_len = match address.prefix_len {
+        Some(prefix_len) => quote! { ::std::option::Option::Some(#prefix_len) },
+        None => quote! { ::std::option::Option::None },
+    };
+    quote! {
+        ::sflowrt_rs_flow::key::key_literal::AddressLiteral {
+            address: #ip,
+            prefix_len: #prefix_len,
+        }
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case("ipsource")]
+    #[case("ipsource,group:ipdestination:internal:\"a b\"")]
+    #[case("group:[country:ip6source]:trusted")]
+    fn test_expand(#[case] input: &str) {
+        assert!(expand_key_def(input).is_ok());
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,", "invalid key definition:\nipsource,\n         ^ expected")]
+    #[case("ipsorce", "unknown key `ipsorce`; did you mean `ipsource`?")]
+    #[case("grop:ipsource:a", "unknown key function `grop`")]
+    #[case("country:ipsource:ip6source", "country expects at most 1 argument")]
+    #[case("country:ip6ttl", "country expects an IP address as argument 1")]
+    fn test_expand_rejects(#[case] input: &str, #[case] expected: &str) {
+        let message = expand_key_def(input).expect_err("should be rejected");
+        assert!(
+            message.contains(expected),
+            "{message:?} should contain {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_integer_tokens() {
+        assert_eq!(integer_tokens(24).to_string(), "24i64");
+        assert_eq!(integer_tokens(-1).to_string(), "- 1i64");
+        assert_eq!(
+            integer_tokens(i64::MIN).to_string(),
+            ":: std :: primitive :: i64 :: MIN"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+//! Benchmarks of the owned and borrowed key definition parsers.
+//!
+//! Run with `cargo bench -p sflowrt-rs-flow`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use sflowrt_rs_flow::key::key_parser::{
+    parse_key_definition_borrowed, parse_key_definition_complete,
+};
+
+/// Key definitions of the sizes and shapes found in a typical flow configuration.
+const DEFINITIONS: &[&str] = &[
+    "ipsource",
+    "ipsource,ipdestination",
+    "ip6source,ip6destination,ip6nexthdr,ip6dscpname",
+    "ipsource,group:ipdestination:internal",
+    "group:[country:ip6source]:trusted:\"partner networks\":external",
+    "mask:ipsource:24,match:ipdestination:{10.0.0.0/8}:/^10\\.1\\./,customkey",
+];
+
+fn bench_parsers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_key_definition");
+    group.throughput(Throughput::Elements(DEFINITIONS.len() as u64));
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            for definition in DEFINITIONS {
+                black_box(parse_key_definition_complete(black_box(definition)).unwrap());
+            }
+        })
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for definition in DEFINITIONS {
+                black_box(parse_key_definition_borrowed(black_box(definition)).unwrap());
+            }
+        })
+    });
+    group.bench_function("borrowed_into_owned", |b| {
+        b.iter(|| {
+            for definition in DEFINITIONS {
+                let borrowed = parse_key_definition_borrowed(black_box(definition)).unwrap();
+                black_box(borrowed.into_owned());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parsers);
+criterion_main!(benches);
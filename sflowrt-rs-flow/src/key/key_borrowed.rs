@@ -0,0 +1,388 @@
+//! A borrowed key definition AST, for parsing many key definitions cheaply.
+//!
+//! Every name and string in the AST in [`super`] is an owned `String`. The trees here
+//! mirror it, but borrow those strings from the text they were parsed from where they
+//! can, so that
+//! [`parse_key_definition_borrowed`](super::key_parser::parse_key_definition_borrowed)
+//! allocates only for the tree's structure (its `Vec`s and `Box`es), and for the few
+//! strings whose escapes had to be rewritten. `into_owned()` converts to the owned AST.
+//!
+//! ```
+//! use std::borrow::Cow;
+//!
+//! use sflowrt_rs_flow::key::{
+//!     key_borrowed::{BorrowedKeyExpression, BorrowedKeyFunction},
+//!     key_parser::parse_key_definition_borrowed,
+//!     KeyDefinition,
+//! };
+//!
+//! let input = "ipsource,group:ipdestination:internal";
+//! let borrowed = parse_key_definition_borrowed(input).unwrap();
+//! let BorrowedKeyExpression::KeyFunction(BorrowedKeyFunction::Group { group_names, .. }) =
+//!     &borrowed.keys[1]
+//! else {
+//!     panic!("expected a group: call");
+//! };
+//! assert!(matches!(group_names[0], Cow::Borrowed("internal")));
+//! assert_eq!(borrowed.into_owned(), input.parse::<KeyDefinition>().unwrap());
+//! ```
+
+use std::borrow::Cow;
+
+use super::key_function::{
+    CountryKeyFunction, CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction,
+};
+use super::key_literal::{AddressLiteral, Literal, LiteralKind};
+use super::key_registry::KeyFunctionPlugin;
+use super::key_signature::{type_call, CallArgument};
+use super::key_span::KeyFunctionKind;
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName, KEY_NAME_TO_VARIANT};
+
+/// A [`KeyDefinition`] borrowing from the text it was parsed from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorrowedKeyDefinition<'a> {
+    pub keys: Vec<BorrowedKeyExpression<'a>>,
+}
+
+impl BorrowedKeyDefinition<'_> {
+    pub fn into_owned(self) -> KeyDefinition {
+        KeyDefinition {
+            keys: self
+                .keys
+                .into_iter()
+                .map(BorrowedKeyExpression::into_owned)
+                .collect(),
+        }
+    }
+}
+
+/// A [`KeyExpression`] borrowing from the text it was parsed from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BorrowedKeyExpression<'a> {
+    KeyName(BorrowedKeyName<'a>),
+    KeyFunction(BorrowedKeyFunction<'a>),
+    Literal(BorrowedLiteral<'a>),
+}
+
+impl<'a> BorrowedKeyExpression<'a> {
+    pub fn into_owned(self) -> KeyExpression {
+        match self {
+            BorrowedKeyExpression::KeyName(key_name) => key_name.into_owned().into(),
+            BorrowedKeyExpression::KeyFunction(function) => function.into_owned().into(),
+            BorrowedKeyExpression::Literal(literal) => literal.into_owned().into(),
+        }
+    }
+
+    /// This argument as a `group:` group name, if it can be one.
+    fn into_group_name(self) -> Option<Cow<'a, str>> {
+        match self {
+            BorrowedKeyExpression::KeyName(name) => Some(name.into_cow()),
+            BorrowedKeyExpression::Literal(BorrowedLiteral::String(name)) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Checking a call to a registered key function converts its arguments to owned ones,
+/// for the plugin.
+impl CallArgument for BorrowedKeyExpression<'_> {
+    fn literal_kind(&self) -> Option<LiteralKind> {
+        match self {
+            BorrowedKeyExpression::Literal(literal) => Some(literal.kind()),
+            _ => None,
+        }
+    }
+
+    fn is_key_name(&self) -> bool {
+        matches!(self, BorrowedKeyExpression::KeyName(_))
+    }
+
+    fn are_accepted_by(plugin: &dyn KeyFunctionPlugin, args: &[Self]) -> bool {
+        let args: Vec<KeyExpression> = args
+            .iter()
+            .cloned()
+            .map(BorrowedKeyExpression::into_owned)
+            .collect();
+        plugin.parse_arguments(&args).is_ok()
+    }
+}
+
+/// A [`KeyName`] whose unknown name, if it is one, is borrowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BorrowedKeyName<'a> {
+    /// A known key name; never [`KeyName::Unknown`].
+    Known(KeyName),
+    Unknown(Cow<'a, str>),
+}
+
+impl<'a> BorrowedKeyName<'a> {
+    /// The key name as it was written in the DSL.
+    pub fn as_str(&self) -> &str {
+        match self {
+            BorrowedKeyName::Known(key_name) => key_name.as_identifier(),
+            BorrowedKeyName::Unknown(name) => name,
+        }
+    }
+
+    pub fn into_owned(self) -> KeyName {
+        match self {
+            BorrowedKeyName::Known(key_name) => key_name,
+            BorrowedKeyName::Unknown(name) => KeyName::Unknown(name.into_owned()),
+        }
+    }
+
+    /// The key name as a string that lives as long as the input it was parsed from.
+    fn into_cow(self) -> Cow<'a, str> {
+        match self {
+            BorrowedKeyName::Known(key_name) => {
+                Cow::Borrowed(key_name.to_sflowrt_key_name().unwrap_or_default())
+            }
+            BorrowedKeyName::Unknown(name) => name,
+        }
+    }
+}
+
+/// The known key name `key_name`, or else an unknown one borrowing `key_name`.
+impl<'a> From<&'a str> for BorrowedKeyName<'a> {
+    fn from(key_name: &'a str) -> Self {
+        match KEY_NAME_TO_VARIANT.get(key_name) {
+            Some(key_name) => BorrowedKeyName::Known(key_name.clone()),
+            None => BorrowedKeyName::Unknown(Cow::Borrowed(key_name)),
+        }
+    }
+}
+
+/// A [`KeyFunction`] borrowing from the text it was parsed from. Each variant has the
+/// fields of the corresponding [`key_function`](super::key_function) struct.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BorrowedKeyFunction<'a> {
+    Group {
+        key: Box<BorrowedKeyExpression<'a>>,
+        group_names: Vec<Cow<'a, str>>,
+    },
+    Country {
+        arg: Cow<'a, str>,
+    },
+    Custom {
+        function_name: Cow<'a, str>,
+        args: Vec<BorrowedKeyExpression<'a>>,
+    },
+    Unknown {
+        function_name: Cow<'a, str>,
+        args: Vec<BorrowedKeyExpression<'a>>,
+    },
+}
+
+impl<'a> BorrowedKeyFunction<'a> {
+    /// A call to `function_name` with `args`, typed as the parser would type the same
+    /// call written in the DSL; see [`KeyFunction::from_call`]. Checking a call to a
+    /// registered key function converts its arguments to owned ones, for the plugin.
+    pub fn from_call(
+        function_name: impl Into<Cow<'a, str>>,
+        mut args: Vec<BorrowedKeyExpression<'a>>,
+    ) -> Self {
+        let function_name = function_name.into();
+        // the signatures guarantee the arguments' shapes for the built-in variants
+        match type_call(&function_name, &args) {
+            KeyFunctionKind::Group => {
+                let key = args.remove(0);
+                BorrowedKeyFunction::Group {
+                    key: Box::new(key),
+                    group_names: args
+                        .into_iter()
+                        .filter_map(BorrowedKeyExpression::into_group_name)
+                        .collect(),
+                }
+            }
+            KeyFunctionKind::Country => match args.pop() {
+                Some(BorrowedKeyExpression::KeyName(key_name)) => BorrowedKeyFunction::Country {
+                    arg: key_name.into_cow(),
+                },
+                _ => unreachable!("arguments should match country:'s signature"),
+            },
+            KeyFunctionKind::Custom => BorrowedKeyFunction::Custom {
+                function_name,
+                args,
+            },
+            KeyFunctionKind::Unknown => BorrowedKeyFunction::Unknown {
+                function_name,
+                args,
+            },
+        }
+    }
+
+    pub fn into_owned(self) -> KeyFunction {
+        match self {
+            BorrowedKeyFunction::Group { key, group_names } => GroupKeyFunction {
+                key: Box::new(key.into_owned()),
+                group_names: group_names.into_iter().map(Cow::into_owned).collect(),
+            }
+            .into(),
+            BorrowedKeyFunction::Country { arg } => CountryKeyFunction {
+                arg: arg.into_owned(),
+            }
+            .into(),
+            BorrowedKeyFunction::Custom {
+                function_name,
+                args,
+            } => CustomKeyFunction {
+                function_name: function_name.into_owned(),
+                args: args
+                    .into_iter()
+                    .map(BorrowedKeyExpression::into_owned)
+                    .collect(),
+            }
+            .into(),
+            BorrowedKeyFunction::Unknown {
+                function_name,
+                args,
+            } => UnknownKeyFunction {
+                function_name: function_name.into_owned(),
+                args: args
+                    .into_iter()
+                    .map(BorrowedKeyExpression::into_owned)
+                    .collect(),
+            }
+            .into(),
+        }
+    }
+}
+
+/// A [`Literal`] borrowing from the text it was parsed from. A string or regular
+/// expression is only owned if it had escapes that had to be rewritten.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BorrowedLiteral<'a> {
+    Integer(i64),
+    String(Cow<'a, str>),
+    Address(AddressLiteral),
+    Regex(Cow<'a, str>),
+}
+
+impl BorrowedLiteral<'_> {
+    pub fn kind(&self) -> LiteralKind {
+        match self {
+            BorrowedLiteral::Integer(_) => LiteralKind::Integer,
+            BorrowedLiteral::String(_) => LiteralKind::String,
+            BorrowedLiteral::Address(_) => LiteralKind::Address,
+            BorrowedLiteral::Regex(_) => LiteralKind::Regex,
+        }
+    }
+
+    pub fn into_owned(self) -> Literal {
+        match self {
+            BorrowedLiteral::Integer(value) => Literal::Integer(value),
+            BorrowedLiteral::String(value) => Literal::String(value.into_owned()),
+            BorrowedLiteral::Address(address) => Literal::Address(address),
+            BorrowedLiteral::Regex(pattern) => Literal::Regex(pattern.into_owned()),
+        }
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{
+        key_parser::{parse_key_definition_borrowed, parse_key_definition_complete},
+        key_registry::{register_key_function, unregister_key_function},
+        key_signature::{
+            ArgumentKind, KeyFunctionSignature, KeyType, SignatureError, TypeConstraint,
+        },
+    };
+
+    /// `lenient:key`; a registered function whose plugin accepts any arguments, even
+    /// ones that don't match its signature.
+    struct LenientKeyFunction;
+
+    impl KeyFunctionPlugin for LenientKeyFunction {
+        fn signature(&self) -> KeyFunctionSignature {
+            KeyFunctionSignature {
+                name: "lenient",
+                args: &[ArgumentKind::KeyExpression],
+                min_arity: 1,
+                max_arity: Some(1),
+                arg_types: &[TypeConstraint::Any],
+                result: KeyType::String,
+            }
+        }
+
+        fn parse_arguments(&self, _args: &[KeyExpression]) -> Result<(), SignatureError> {
+            Ok(())
+        }
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource")]
+    #[case("ipsource,ip6_offset,mykey")]
+    #[case("group:[country:ip6source]:trusted:\"a,b\":\"say \\\"hi\\\"\"")]
+    #[case("group:ipsource,country:[country:ipsource],country:ipsource:ip6source")]
+    #[case("unknownfunc:ipsource:[group:ipsource:a]:-24:{fe80::/10}:10.0.0.0/8:/x\\/y\\d/")]
+    fn test_borrowed_matches_owned(#[case] input: &str) {
+        let borrowed = parse_key_definition_borrowed(input).expect("should parse");
+        assert_eq!(
+            Ok(borrowed.into_owned()),
+            parse_key_definition_complete(input)
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,")]
+    #[case("group:ipsource:\"a")]
+    #[case("f:ipsource:")]
+    #[case("ipsource:")]
+    #[case("f:[g:{10.0.0.300}]")]
+    #[case("f:ipsource:\"a\\q\"")]
+    fn test_borrowed_errors_match_owned(#[case] input: &str) {
+        assert_eq!(
+            parse_key_definition_borrowed(input).map(BorrowedKeyDefinition::into_owned),
+            parse_key_definition_complete(input)
+        );
+    }
+
+    #[test]
+    fn test_borrowed_matches_owned_for_registered_function() {
+        register_key_function(LenientKeyFunction).expect("should register");
+        let input = "lenient:ipsource,lenient:ipsource:ip6source,lenient:24";
+        let borrowed = parse_key_definition_borrowed(input).map(BorrowedKeyDefinition::into_owned);
+        let owned = parse_key_definition_complete(input);
+        assert!(unregister_key_function("lenient"));
+        let owned = owned.expect("should parse");
+        assert_eq!(borrowed, Ok(owned.clone()));
+        // only the call that matches the signature is the registered function's
+        let kinds: Vec<bool> = owned
+            .keys
+            .iter()
+            .map(|key| matches!(key, KeyExpression::KeyFunction(KeyFunction::Custom(_))))
+            .collect();
+        assert_eq!(kinds, vec![true, false, false]);
+    }
+
+    #[test]
+    fn test_borrows_from_input() {
+        let input = "mykey,group:ipsource:a:\"b c\":\"d\\\\e\",f:/x/:/x\\/y/";
+        let borrowed = parse_key_definition_borrowed(input).expect("should parse");
+        let is_borrowed = |cow: &Cow<str>| matches!(cow, Cow::Borrowed(_));
+        let [BorrowedKeyExpression::KeyName(BorrowedKeyName::Unknown(name)), BorrowedKeyExpression::KeyFunction(BorrowedKeyFunction::Group { group_names, .. }), BorrowedKeyExpression::KeyFunction(BorrowedKeyFunction::Unknown {
+            function_name,
+            args,
+        })] = borrowed.keys.as_slice()
+        else {
+            panic!("unexpected parse: {borrowed:?}");
+        };
+        assert!(is_borrowed(name));
+        assert!(is_borrowed(function_name));
+        // only the strings that had escapes rewritten are owned
+        let group_names: Vec<bool> = group_names.iter().map(is_borrowed).collect();
+        assert_eq!(group_names, vec![true, true, false]);
+        let regexes: Vec<bool> = args
+            .iter()
+            .map(|arg| match arg {
+                BorrowedKeyExpression::Literal(BorrowedLiteral::Regex(pattern)) => {
+                    is_borrowed(pattern)
+                }
+                _ => panic!("expected a regex, got {arg:?}"),
+            })
+            .collect();
+        assert_eq!(regexes, vec![true, false]);
+    }
+}
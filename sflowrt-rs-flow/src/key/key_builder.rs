@@ -0,0 +1,203 @@
+//! Fluent builders for key definitions and key function calls.
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{key_literal::Literal, KeyDefinition, KeyFunction, KeyName};
+//!
+//! let definition = KeyDefinition::builder()
+//!     .key(KeyName::IpSource)
+//!     .group(KeyName::Ip6Source, ["trusted", "external"])
+//!     .key(
+//!         KeyFunction::call("mask")
+//!             .arg(KeyName::IpSource)
+//!             .arg(Literal::Integer(24)),
+//!     )
+//!     .build();
+//! assert_eq!(
+//!     definition.to_string(),
+//!     "ipsource,group:ip6source:trusted:external,mask:ipsource:24"
+//! );
+//! ```
+
+use super::key_function::{
+    CountryKeyFunction, CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction,
+};
+use super::key_signature::type_call;
+use super::key_span::KeyFunctionKind;
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// A builder for a [`KeyDefinition`]; see [`KeyDefinition::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct KeyDefinitionBuilder {
+    keys: Vec<KeyExpression>,
+}
+
+impl KeyDefinitionBuilder {
+    /// Add a key expression: a key name, a key function, or a key function call
+    /// builder.
+    pub fn key(mut self, key: impl Into<KeyExpression>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Add a `group:` call, grouping `key` into the named groups.
+    pub fn group(
+        self,
+        key: impl Into<KeyExpression>,
+        group_names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.key(KeyFunction::Group(GroupKeyFunction::new(key, group_names)))
+    }
+
+    /// Add a `country:` call on `key_name`.
+    pub fn country(self, key_name: impl Into<KeyName>) -> Self {
+        self.key(KeyFunction::Country(CountryKeyFunction::new(key_name)))
+    }
+
+    pub fn build(self) -> KeyDefinition {
+        KeyDefinition::new(self.keys)
+    }
+}
+
+/// A builder for a call to a key function; see [`KeyFunction::call`].
+#[derive(Clone, Debug)]
+pub struct KeyFunctionCallBuilder {
+    function_name: String,
+    args: Vec<KeyExpression>,
+}
+
+impl KeyFunctionCallBuilder {
+    pub fn arg(mut self, arg: impl Into<KeyExpression>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<KeyExpression>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Build the call; see [`KeyFunction::from_call`].
+    pub fn build(self) -> KeyFunction {
+        KeyFunction::from_call(self.function_name, self.args)
+    }
+}
+
+impl From<KeyFunctionCallBuilder> for KeyFunction {
+    fn from(builder: KeyFunctionCallBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl From<KeyFunctionCallBuilder> for KeyExpression {
+    fn from(builder: KeyFunctionCallBuilder) -> Self {
+        builder.build().into()
+    }
+}
+
+impl KeyFunction {
+    /// Start building a call to the key function `function_name`.
+    pub fn call(function_name: impl Into<String>) -> KeyFunctionCallBuilder {
+        KeyFunctionCallBuilder {
+            function_name: function_name.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// A call to `function_name` with `args`, typed as the parser would type the same
+    /// call written in the DSL: a built-in or registered function whose arguments
+    /// match its signature gets its own variant, and anything else is
+    /// [`KeyFunction::Unknown`].
+    pub fn from_call(function_name: impl Into<String>, args: Vec<KeyExpression>) -> Self {
+        let function_name = function_name.into();
+        // the signatures guarantee the arguments' shapes for the built-in variants
+        match type_call(&function_name, &args) {
+            KeyFunctionKind::Group => GroupKeyFunction::from_arguments(args)
+                .expect("arguments should match group:'s signature")
+                .into(),
+            KeyFunctionKind::Country => CountryKeyFunction::from_arguments(args)
+                .expect("arguments should match country:'s signature")
+                .into(),
+            KeyFunctionKind::Custom => CustomKeyFunction {
+                function_name,
+                args,
+            }
+            .into(),
+            KeyFunctionKind::Unknown => UnknownKeyFunction {
+                function_name,
+                args,
+            }
+            .into(),
+        }
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::key_literal::Literal;
+
+    #[rstest::rstest]
+    #[case("group:ipsource:trusted:\"a b\"")]
+    #[case("group:ipsource")]
+    #[case("group:24:trusted")]
+    #[case("country:ip6source")]
+    #[case("country:ipsource:ip6source")]
+    #[case("f:ipsource:[country:ipsource]:{10.0.0.0/8}")]
+    fn test_from_call_types_like_the_parser(#[case] input: &str) {
+        let parsed: KeyDefinition = input.parse().expect("should parse");
+        let KeyExpression::KeyFunction(parsed) = &parsed.keys()[0] else {
+            panic!("expected a key function, got {parsed:?}");
+        };
+        let (function_name, args) = match parsed {
+            KeyFunction::Unknown(UnknownKeyFunction {
+                function_name,
+                args,
+            }) => (function_name.clone(), args.clone()),
+            KeyFunction::Group(group) => {
+                let mut args = vec![(*group.key).clone()];
+                args.extend(
+                    group
+                        .group_names
+                        .iter()
+                        .map(|name| Literal::String(name.clone()).into()),
+                );
+                ("group".to_string(), args)
+            }
+            KeyFunction::Country(country) => (
+                "country".to_string(),
+                vec![KeyName::from(country.arg.as_str()).into()],
+            ),
+            KeyFunction::Custom(_) => unreachable!("no functions are registered"),
+        };
+        assert_eq!(&KeyFunction::from_call(function_name, args), parsed);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut definition = KeyDefinition::builder()
+            .key(KeyName::IpSource)
+            .group(
+                KeyFunction::call("country").arg(KeyName::Ip6Source),
+                ["a", "b"],
+            )
+            .country("mykey")
+            .build();
+        definition.push(KeyFunction::call("group").arg(KeyName::IpSource));
+        assert_eq!(
+            definition.to_string(),
+            "ipsource,group:[country:ip6source]:a:b,country:mykey,group:ipsource"
+        );
+        assert_eq!(definition.len(), 4);
+        assert!(matches!(
+            definition.keys()[1],
+            KeyExpression::KeyFunction(KeyFunction::Group(_))
+        ));
+        assert!(matches!(
+            definition.iter().last(),
+            Some(KeyExpression::KeyFunction(KeyFunction::Unknown(_)))
+        ));
+        let names: Vec<String> = definition.into_iter().map(|key| key.to_string()).collect();
+        assert_eq!(names[0], "ipsource");
+    }
+}
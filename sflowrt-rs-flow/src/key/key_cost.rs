@@ -0,0 +1,397 @@
+//! Rough cardinality and memory cost estimates for key definitions.
+//!
+//! Every known key has a cardinality hint: the base-2 logarithm of how many distinct
+//! values it can have (see [`KeyName::cardinality_bits`]). A flow is a combination of
+//! its key expressions' values, so a key definition can produce as many flows as the
+//! product of its key expressions' cardinalities; in bits, their sum. Key functions
+//! can reduce a key's cardinality: `group:` to the number of groups, `country:` to the
+//! number of countries, and `mask:` to the number of prefixes of its length.
+//! [`CostModel::estimate`] works this out, along with the memory a cache of every
+//! flow would take:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{key_cost::CostModel, KeyDefinition};
+//!
+//! let definition: KeyDefinition = "group:ipsource:a:b,mask:ipdestination:16".parse().unwrap();
+//! let estimate = CostModel::default().estimate(&definition);
+//! assert_eq!(estimate.flow_bits, 17.0);
+//! assert!(estimate.warnings.is_empty());
+//! ```
+//!
+//! These are upper bounds on what the keys' values allow, not predictions of the
+//! traffic; and unknown keys and key functions are guessed at, with a [`CostWarning`]
+//! saying so.
+
+use std::fmt;
+
+use super::key_literal::Literal;
+use super::key_signature::{KeyType, KEY_FUNCTION_NAME_MASK, KEY_FUNCTION_NAME_OR};
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// The number of values `country:` can produce; roughly the number of ISO 3166
+/// country codes.
+const COUNTRIES: f64 = 250.0;
+
+impl KeyName {
+    /// The base-2 logarithm of the number of distinct values this key can have, or
+    /// `None` for an unknown key.
+    pub fn cardinality_bits(&self) -> Option<f64> {
+        let bits = match self {
+            KeyName::IpSource | KeyName::IpDestination => 32.0,
+            KeyName::Ip6Source | KeyName::Ip6Destination => 128.0,
+            KeyName::Ip6FlowLabel => 20.0,
+            KeyName::Ip6Offset | KeyName::Ip6Bytes => 16.0,
+            // a list of next header values; in practice, there are few extension headers
+            KeyName::Ip6Extensions => 16.0,
+            KeyName::Ip6FragmentOffset => 13.0,
+            KeyName::Ip6TOS | KeyName::Ip6TTL | KeyName::Ip6NextHeader => 8.0,
+            KeyName::Ip6DSCP | KeyName::Ip6DSCPName => 6.0,
+            KeyName::Ip6ECN => 2.0,
+            KeyName::Ip6FragmentMFlag => 1.0,
+            KeyName::Unknown(_) => return None,
+        };
+        Some(bits)
+    }
+}
+
+/// The assumptions a cost estimate is made with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostModel {
+    /// The cardinality assumed for an unknown key, in bits. Default: 16.
+    pub unknown_key_bits: f64,
+    /// The memory a cached flow takes besides its key's values (e.g. its counters and
+    /// the cache's bookkeeping), in bytes. Default: 128.
+    pub entry_overhead_bytes: usize,
+    /// The number of flows above which an estimate warns. Default: one million.
+    pub max_flows: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            unknown_key_bits: 16.0,
+            entry_overhead_bytes: 128,
+            max_flows: 1e6,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn with_unknown_key_bits(mut self, unknown_key_bits: f64) -> Self {
+        self.unknown_key_bits = unknown_key_bits;
+        self
+    }
+
+    pub fn with_entry_overhead_bytes(mut self, entry_overhead_bytes: usize) -> Self {
+        self.entry_overhead_bytes = entry_overhead_bytes;
+        self
+    }
+
+    pub fn with_max_flows(mut self, max_flows: f64) -> Self {
+        self.max_flows = max_flows;
+        self
+    }
+
+    /// Estimate how many flows `definition` can produce, and how much memory caching
+    /// them would take; see the [module docs](self).
+    pub fn estimate(&self, definition: &KeyDefinition) -> CostEstimate {
+        let mut warnings = Vec::new();
+        let (mut flow_bits, mut value_bytes) = (0.0, 0);
+        for key in definition {
+            let estimate = self.estimate_expression(key, &mut warnings);
+            flow_bits += estimate.bits;
+            value_bytes += estimate.value_len;
+        }
+        // the values are joined with `,` into the flow's key
+        let bytes_per_flow =
+            self.entry_overhead_bytes + value_bytes + definition.len().saturating_sub(1);
+        if flow_bits.exp2() > self.max_flows {
+            warnings.push(CostWarning::TooManyFlows {
+                flows: flow_bits.exp2(),
+                max_flows: self.max_flows,
+            });
+        }
+        CostEstimate {
+            flow_bits,
+            bytes_per_flow,
+            warnings,
+        }
+    }
+
+    fn estimate_expression(
+        &self,
+        expression: &KeyExpression,
+        warnings: &mut Vec<CostWarning>,
+    ) -> ValueEstimate {
+        match expression {
+            KeyExpression::KeyName(key_name) => self.estimate_key_name(key_name, warnings),
+            KeyExpression::KeyFunction(function) => self.estimate_function(function, warnings),
+            KeyExpression::Literal(literal) => ValueEstimate {
+                bits: 0.0,
+                value_len: literal.to_string().len(),
+            },
+        }
+    }
+
+    fn estimate_key_name(
+        &self,
+        key_name: &KeyName,
+        warnings: &mut Vec<CostWarning>,
+    ) -> ValueEstimate {
+        let Some(bits) = key_name.cardinality_bits() else {
+            push_unique(warnings, CostWarning::UnknownKey(key_name.clone()));
+            return ValueEstimate {
+                bits: self.unknown_key_bits,
+                value_len: UNKNOWN_VALUE_LEN,
+            };
+        };
+        let value_len = match key_name.key_type() {
+            Some(KeyType::Ipv4Address) => "255.255.255.255".len(),
+            Some(KeyType::Ipv6Address) => "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".len(),
+            Some(KeyType::Integer) => decimal_digits(bits),
+            Some(KeyType::Bits) => bits as usize,
+            Some(KeyType::Boolean) => "false".len(),
+            Some(KeyType::String | KeyType::List) | None => UNKNOWN_VALUE_LEN,
+        };
+        ValueEstimate { bits, value_len }
+    }
+
+    fn estimate_function(
+        &self,
+        function: &KeyFunction,
+        warnings: &mut Vec<CostWarning>,
+    ) -> ValueEstimate {
+        let (function_name, args) = match function {
+            KeyFunction::Group(group) => {
+                let key = self.estimate_expression(&group.key, warnings);
+                let groups = group.group_names.len().max(1) as f64;
+                return ValueEstimate {
+                    bits: key.bits.min(groups.log2()),
+                    value_len: group.group_names.iter().map(String::len).max().unwrap_or(0),
+                };
+            }
+            KeyFunction::Country(country) => {
+                let key = self.estimate_key_name(&KeyName::from(country.arg.as_str()), warnings);
+                return ValueEstimate {
+                    bits: key.bits.min(COUNTRIES.log2()),
+                    value_len: 2,
+                };
+            }
+            KeyFunction::Custom(custom) => (&custom.function_name, &custom.args),
+            KeyFunction::Unknown(unknown) => (&unknown.function_name, &unknown.args),
+        };
+        let estimates: Vec<ValueEstimate> = args
+            .iter()
+            .map(|arg| self.estimate_expression(arg, warnings))
+            .collect();
+        // only a call that matches `mask:`'s or `or:`'s signature is one of theirs
+        let is_builtin = matches!(function, KeyFunction::Custom(_));
+        match (function_name.as_str(), args.as_slice()) {
+            // a prefix of the key's address
+            (KEY_FUNCTION_NAME_MASK, [_, KeyExpression::Literal(Literal::Integer(prefix_len))])
+                if is_builtin && *prefix_len >= 0 =>
+            {
+                ValueEstimate {
+                    bits: estimates[0].bits.min(*prefix_len as f64),
+                    value_len: estimates[0].value_len,
+                }
+            }
+            // the value of any one of the alternatives
+            (KEY_FUNCTION_NAME_OR, _) if is_builtin => ValueEstimate {
+                bits: estimates
+                    .iter()
+                    .map(|e| e.bits.exp2())
+                    .sum::<f64>()
+                    .max(1.0)
+                    .log2(),
+                value_len: estimates.iter().map(|e| e.value_len).max().unwrap_or(0),
+            },
+            // at most one value per combination of the arguments' values
+            _ => {
+                push_unique(
+                    warnings,
+                    CostWarning::UnmodelledFunction(function_name.clone()),
+                );
+                ValueEstimate {
+                    bits: estimates.iter().map(|e| e.bits).sum(),
+                    value_len: estimates.iter().map(|e| e.value_len).sum(),
+                }
+            }
+        }
+    }
+}
+
+/// The length assumed for a value whose length isn't known, in bytes.
+const UNKNOWN_VALUE_LEN: usize = 16;
+
+/// The cardinality and longest value of a key expression.
+struct ValueEstimate {
+    bits: f64,
+    value_len: usize,
+}
+
+/// The number of decimal digits of the largest value with `bits` bits.
+fn decimal_digits(bits: f64) -> usize {
+    ((bits * std::f64::consts::LOG10_2).ceil() as usize).max(1)
+}
+
+fn push_unique(warnings: &mut Vec<CostWarning>, warning: CostWarning) {
+    if !warnings.contains(&warning) {
+        warnings.push(warning);
+    }
+}
+
+/// A cost estimate for a key definition, from [`CostModel::estimate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostEstimate {
+    /// The base-2 logarithm of the number of distinct flows the key definition can
+    /// produce.
+    pub flow_bits: f64,
+    /// The memory a cached flow takes, in bytes.
+    pub bytes_per_flow: usize,
+    pub warnings: Vec<CostWarning>,
+}
+
+impl CostEstimate {
+    /// The number of distinct flows the key definition can produce; infinite if that
+    /// is too large for an `f64`.
+    pub fn flows(&self) -> f64 {
+        self.flow_bits.exp2()
+    }
+
+    /// The memory a cache of every flow would take, in bytes.
+    pub fn memory_bytes(&self) -> f64 {
+        self.flows() * self.bytes_per_flow as f64
+    }
+}
+
+/// Writes the estimate, then each warning on a line of its own.
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "up to {:.3e} flows (2^{:.1}) of {} bytes each; {:.3e} bytes in all",
+            self.flows(),
+            self.flow_bits,
+            self.bytes_per_flow,
+            self.memory_bytes()
+        )?;
+        for warning in &self.warnings {
+            write!(f, "\nwarning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A guess, or a cause for concern, in a cost estimate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CostWarning {
+    /// An unknown key, assumed to have [`CostModel::unknown_key_bits`] bits of
+    /// cardinality.
+    UnknownKey(KeyName),
+    /// A call to a key function with no cardinality model, assumed to produce at most
+    /// one value per combination of its arguments' values.
+    UnmodelledFunction(String),
+    /// More flows than [`CostModel::max_flows`].
+    TooManyFlows { flows: f64, max_flows: f64 },
+}
+
+impl fmt::Display for CostWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostWarning::UnknownKey(key_name) => {
+                write!(f, "unknown key `{key_name}`; its cardinality is a guess")
+            }
+            CostWarning::UnmodelledFunction(function_name) => write!(
+                f,
+                "no cardinality model for `{function_name}:`; assuming one value per \
+                 combination of its arguments"
+            ),
+            CostWarning::TooManyFlows { flows, max_flows } => write!(
+                f,
+                "up to {flows:.3e} flows, more than the maximum of {max_flows:.3e}"
+            ),
+        }
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn estimate(input: &str) -> CostEstimate {
+        let definition: KeyDefinition = input.parse().expect("should parse");
+        CostModel::default()
+            .with_max_flows(f64::INFINITY)
+            .estimate(&definition)
+    }
+
+    #[rstest]
+    #[case("ipsource", 32.0)]
+    #[case("ipsource,ipdestination", 64.0)]
+    #[case("ip6source,ip6dscp,ip6fragm", 135.0)]
+    #[case("group:ipsource:a:b:c:d", 2.0)]
+    #[case("group:ip6fragm:a:b:c:d", 1.0)]
+    #[case("country:ip6source,ip6ecn", COUNTRIES.log2() + 2.0)]
+    #[case("mask:ipsource:24", 24.0)]
+    #[case("or:ip6ecn:ip6ecn", 3.0)]
+    #[case("or:ipsource", 32.0)]
+    fn test_flow_bits(#[case] input: &str, #[case] flow_bits: f64) {
+        let estimate = estimate(input);
+        assert!(
+            (estimate.flow_bits - flow_bits).abs() < 1e-9,
+            "{} != {flow_bits}",
+            estimate.flow_bits
+        );
+        assert_eq!(estimate.warnings, vec![]);
+    }
+
+    #[rstest]
+    #[case("ipsource", 128 + 15)]
+    #[case("ipsource,ip6ttl,ip6fragm", 128 + 15 + 3 + 5 + 2)]
+    #[case("group:ipsource:a:longer", 128 + 6)]
+    fn test_bytes_per_flow(#[case] input: &str, #[case] bytes_per_flow: usize) {
+        assert_eq!(estimate(input).bytes_per_flow, bytes_per_flow);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let estimate = estimate("mykey,f:mykey:ipsource,mask:ipsource:x,or:ipsource:24");
+        // a literal argument has a single value
+        assert_eq!(
+            estimate.flow_bits,
+            16.0 + (16.0 + 32.0) + (32.0 + 16.0) + 32.0
+        );
+        assert_eq!(
+            estimate.warnings,
+            vec![
+                CostWarning::UnknownKey("mykey".into()),
+                CostWarning::UnmodelledFunction("f".to_string()),
+                CostWarning::UnknownKey("x".into()),
+                CostWarning::UnmodelledFunction("mask".to_string()),
+                CostWarning::UnmodelledFunction("or".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_too_many_flows() {
+        let definition: KeyDefinition = "ipsource,ip6ttl".parse().unwrap();
+        let estimate = CostModel::default().estimate(&definition);
+        assert_eq!(
+            estimate.warnings,
+            vec![CostWarning::TooManyFlows {
+                flows: 2f64.powi(40),
+                max_flows: 1e6
+            }]
+        );
+        assert_eq!(
+            estimate.to_string(),
+            "up to 1.100e12 flows (2^40.0) of 147 bytes each; 1.616e14 bytes in all\n\
+             warning: up to 1.100e12 flows, more than the maximum of 1.000e6"
+        );
+    }
+}
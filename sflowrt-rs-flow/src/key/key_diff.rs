@@ -0,0 +1,551 @@
+//! Structural diffs between key definitions.
+//!
+//! [`KeyDefinition::diff`] compares two key definitions as trees rather than as text,
+//! and reports what changed as a list of [`KeyEdit`]s: key expressions and arguments
+//! added, removed, moved or replaced, and group names added, removed or renamed. Each
+//! edit has a [`KeyPath`] to where in the tree it happened, and renders as one
+//! human-readable line:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::KeyDefinition;
+//!
+//! let old: KeyDefinition = "ipsource,group:ipdestination:internal,ipprotocol".parse().unwrap();
+//! let new: KeyDefinition = "group:ipdestination:trusted,ipsource,tcpdestinationport"
+//!     .parse()
+//!     .unwrap();
+//! assert_eq!(
+//!     old.diff(&new).to_string(),
+//!     "moved key 1 to 0: `group:ipdestination:trusted`\n\
+//!      renamed group 0.1 `internal` to `trusted`\n\
+//!      replaced key 2 `ipprotocol` with `tcpdestinationport`"
+//! );
+//! ```
+//!
+//! The longest run of key expressions that are in both definitions, in the same
+//! relative order, is unchanged. Of the rest, an expression on both sides, or else a
+//! call to the same key function on both sides, corresponds; the calls are diffed
+//! argument by argument, and either is moved if its siblings are now on its other
+//! side. What's left at the same place on both sides is replaced, and anything left
+//! over was added or removed.
+
+use std::fmt;
+use std::ops::Range;
+
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// A position in a key definition's tree: the index of a top-level key expression,
+/// then, for each level of key function calls, the index of an argument. A `group:`
+/// call's key expression is its argument 0, followed by its group names; a
+/// `country:` call's key name is its argument 0.
+///
+/// Written as the indices joined by `.`; e.g. `1.0` is the first argument of the
+/// second key expression.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyPath(Vec<usize>);
+
+impl KeyPath {
+    pub fn new(indices: Vec<usize>) -> Self {
+        Self(indices)
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.0
+    }
+
+    /// Whether this is the path of a top-level key expression, rather than of an
+    /// argument.
+    pub fn is_key(&self) -> bool {
+        self.0.len() == 1
+    }
+
+    fn child(&self, index: usize) -> Self {
+        let mut indices = self.0.clone();
+        indices.push(index);
+        Self(indices)
+    }
+
+    fn noun(&self) -> &'static str {
+        if self.is_key() {
+            "key"
+        } else {
+            "argument"
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, index) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{index}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One change between two key definitions.
+///
+/// Paths lead into the new key definition, except that the last index of a removed
+/// expression or group name, and the `from` of a move, is its index in the old one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyEdit {
+    Added {
+        path: KeyPath,
+        expression: KeyExpression,
+    },
+    Removed {
+        path: KeyPath,
+        expression: KeyExpression,
+    },
+    /// An expression that moved relative to its siblings. Any changes to it are
+    /// separate edits, at its new path.
+    Moved {
+        from: KeyPath,
+        to: KeyPath,
+        expression: KeyExpression,
+    },
+    /// An expression replaced by another that isn't a call to the same key function.
+    Replaced {
+        path: KeyPath,
+        old: KeyExpression,
+        new: KeyExpression,
+    },
+    GroupAdded {
+        path: KeyPath,
+        name: String,
+    },
+    GroupRemoved {
+        path: KeyPath,
+        name: String,
+    },
+    GroupRenamed {
+        path: KeyPath,
+        old: String,
+        new: String,
+    },
+}
+
+impl KeyEdit {
+    /// Where the edit happened; for a move, where the expression moved to.
+    pub fn path(&self) -> &KeyPath {
+        match self {
+            KeyEdit::Added { path, .. }
+            | KeyEdit::Removed { path, .. }
+            | KeyEdit::Replaced { path, .. }
+            | KeyEdit::GroupAdded { path, .. }
+            | KeyEdit::GroupRemoved { path, .. }
+            | KeyEdit::GroupRenamed { path, .. } => path,
+            KeyEdit::Moved { to, .. } => to,
+        }
+    }
+}
+
+impl fmt::Display for KeyEdit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyEdit::Added { path, expression } => {
+                write!(f, "added {} {path}: `{expression}`", path.noun())
+            }
+            KeyEdit::Removed { path, expression } => {
+                write!(f, "removed {} {path}: `{expression}`", path.noun())
+            }
+            KeyEdit::Moved {
+                from,
+                to,
+                expression,
+            } => write!(f, "moved {} {from} to {to}: `{expression}`", to.noun()),
+            KeyEdit::Replaced { path, old, new } => {
+                write!(f, "replaced {} {path} `{old}` with `{new}`", path.noun())
+            }
+            KeyEdit::GroupAdded { path, name } => write!(f, "added group {path} `{name}`"),
+            KeyEdit::GroupRemoved { path, name } => write!(f, "removed group {path} `{name}`"),
+            KeyEdit::GroupRenamed { path, old, new } => {
+                write!(f, "renamed group {path} `{old}` to `{new}`")
+            }
+        }
+    }
+}
+
+/// The changes between two key definitions, from [`KeyDefinition::diff`]. Empty if
+/// they're equal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyDiff {
+    edits: Vec<KeyEdit>,
+}
+
+impl KeyDiff {
+    pub fn edits(&self) -> &[KeyEdit] {
+        &self.edits
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, KeyEdit> {
+        self.edits.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn into_edits(self) -> Vec<KeyEdit> {
+        self.edits
+    }
+}
+
+impl<'a> IntoIterator for &'a KeyDiff {
+    type Item = &'a KeyEdit;
+    type IntoIter = std::slice::Iter<'a, KeyEdit>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.edits.iter()
+    }
+}
+
+/// Writes one edit per line.
+impl fmt::Display for KeyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, edit) in self.edits.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{edit}")?;
+        }
+        Ok(())
+    }
+}
+
+impl KeyDefinition {
+    /// The structural changes from this key definition to `new`; see
+    /// [`super::key_diff`].
+    pub fn diff(&self, new: &KeyDefinition) -> KeyDiff {
+        let mut edits = Vec::new();
+        diff_expressions(self.keys(), new.keys(), &KeyPath::default(), &mut edits);
+        KeyDiff { edits }
+    }
+}
+
+/// Diff two lists of sibling expressions, under `parent`.
+fn diff_expressions(
+    old: &[KeyExpression],
+    new: &[KeyExpression],
+    parent: &KeyPath,
+    edits: &mut Vec<KeyEdit>,
+) {
+    let common = common_subsequence(old.len(), new.len(), |i, j| old[i] == new[j]);
+    let mut matches: Vec<Option<usize>> = vec![None; old.len()];
+    let mut new_matched: Vec<bool> = vec![false; new.len()];
+    for &(i, j) in &common {
+        matches[i] = Some(j);
+        new_matched[j] = true;
+    }
+    // of the rest, an expression on both sides moved, and otherwise calls to the same
+    // key function correspond (and are diffed argument by argument)
+    for same_call in [false, true] {
+        for i in 0..old.len() {
+            if matches[i].is_some() {
+                continue;
+            }
+            let corresponds = |j: usize| {
+                if same_call {
+                    is_same_call(&old[i], &new[j])
+                } else {
+                    old[i] == new[j]
+                }
+            };
+            if let Some(j) = (0..new.len()).find(|&j| !new_matched[j] && corresponds(j)) {
+                matches[i] = Some(j);
+                new_matched[j] = true;
+            }
+        }
+    }
+
+    let segments = segments(common, old.len(), new.len());
+    // which segment each expression on the new side is in, to tell moves apart
+    let mut new_segment = vec![0; new.len()];
+    for (segment, (_, new_gap)) in segments.iter().enumerate() {
+        new_segment[new_gap.clone()].fill(segment);
+    }
+    for (segment, (old_gap, new_gap)) in segments.into_iter().enumerate() {
+        // anything else is paired up by position
+        let mut new_rest = new_gap.filter(|&j| !new_matched[j]);
+        for i in old_gap {
+            match matches[i].or_else(|| new_rest.next()) {
+                Some(j) if new_segment[j] != segment => {
+                    edits.push(KeyEdit::Moved {
+                        from: parent.child(i),
+                        to: parent.child(j),
+                        expression: new[j].clone(),
+                    });
+                    diff_expression(&old[i], &new[j], parent.child(j), edits);
+                }
+                Some(j) => diff_expression(&old[i], &new[j], parent.child(j), edits),
+                None => edits.push(KeyEdit::Removed {
+                    path: parent.child(i),
+                    expression: old[i].clone(),
+                }),
+            }
+        }
+        for j in new_rest {
+            edits.push(KeyEdit::Added {
+                path: parent.child(j),
+                expression: new[j].clone(),
+            });
+        }
+    }
+}
+
+/// Whether two expressions are calls to the same key function.
+fn is_same_call(old: &KeyExpression, new: &KeyExpression) -> bool {
+    let (KeyExpression::KeyFunction(old), KeyExpression::KeyFunction(new)) = (old, new) else {
+        return false;
+    };
+    match (old, new) {
+        (KeyFunction::Group(_), KeyFunction::Group(_))
+        | (KeyFunction::Country(_), KeyFunction::Country(_)) => true,
+        (KeyFunction::Custom(old), KeyFunction::Custom(new)) => {
+            old.function_name == new.function_name
+        }
+        (KeyFunction::Unknown(old), KeyFunction::Unknown(new)) => {
+            old.function_name == new.function_name
+        }
+        _ => false,
+    }
+}
+
+/// Diff two expressions at the same place, `path`.
+fn diff_expression(
+    old: &KeyExpression,
+    new: &KeyExpression,
+    path: KeyPath,
+    edits: &mut Vec<KeyEdit>,
+) {
+    if old == new {
+        return;
+    }
+    let (KeyExpression::KeyFunction(old_function), KeyExpression::KeyFunction(new_function)) =
+        (old, new)
+    else {
+        edits.push(replaced(path, old, new));
+        return;
+    };
+    match (old_function, new_function) {
+        (KeyFunction::Group(old_group), KeyFunction::Group(new_group)) => {
+            diff_expression(&old_group.key, &new_group.key, path.child(0), edits);
+            diff_group_names(&old_group.group_names, &new_group.group_names, &path, edits);
+        }
+        (KeyFunction::Country(old_country), KeyFunction::Country(new_country)) => {
+            edits.push(KeyEdit::Replaced {
+                path: path.child(0),
+                old: KeyName::from(old_country.arg.as_str()).into(),
+                new: KeyName::from(new_country.arg.as_str()).into(),
+            });
+        }
+        (KeyFunction::Custom(old_custom), KeyFunction::Custom(new_custom))
+            if old_custom.function_name == new_custom.function_name =>
+        {
+            diff_expressions(&old_custom.args, &new_custom.args, &path, edits);
+        }
+        (KeyFunction::Unknown(old_unknown), KeyFunction::Unknown(new_unknown))
+            if old_unknown.function_name == new_unknown.function_name =>
+        {
+            diff_expressions(&old_unknown.args, &new_unknown.args, &path, edits);
+        }
+        _ => edits.push(replaced(path, old, new)),
+    }
+}
+
+fn replaced(path: KeyPath, old: &KeyExpression, new: &KeyExpression) -> KeyEdit {
+    KeyEdit::Replaced {
+        path,
+        old: old.clone(),
+        new: new.clone(),
+    }
+}
+
+/// Diff the group names of two `group:` calls at `path`. The group names follow the
+/// key expression, so the group name at index `i` is argument `i + 1`.
+fn diff_group_names(old: &[String], new: &[String], path: &KeyPath, edits: &mut Vec<KeyEdit>) {
+    let common = common_subsequence(old.len(), new.len(), |i, j| old[i] == new[j]);
+    for (old_gap, new_gap) in segments(common, old.len(), new.len()) {
+        for (i, j) in old_gap.clone().zip(new_gap.clone()) {
+            edits.push(KeyEdit::GroupRenamed {
+                path: path.child(j + 1),
+                old: old[i].clone(),
+                new: new[j].clone(),
+            });
+        }
+        for i in old_gap.clone().skip(new_gap.len()) {
+            edits.push(KeyEdit::GroupRemoved {
+                path: path.child(i + 1),
+                name: old[i].clone(),
+            });
+        }
+        for j in new_gap.skip(old_gap.len()) {
+            edits.push(KeyEdit::GroupAdded {
+                path: path.child(j + 1),
+                name: new[j].clone(),
+            });
+        }
+    }
+}
+
+/// The index pairs of a longest common subsequence of two sequences, of lengths
+/// `old_len` and `new_len`, in order; `eq(i, j)` compares their elements.
+fn common_subsequence(
+    old_len: usize,
+    new_len: usize,
+    eq: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and
+    // new[j..]; key definitions are short, so the quadratic table is fine
+    let mut lengths = vec![vec![0usize; new_len + 1]; old_len + 1];
+    for i in (0..old_len).rev() {
+        for j in (0..new_len).rev() {
+            lengths[i][j] = if eq(i, j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::with_capacity(lengths[0][0]);
+    let (mut i, mut j) = (0, 0);
+    while i < old_len && j < new_len {
+        if eq(i, j) {
+            pairs.push((i, j));
+            (i, j) = (i + 1, j + 1);
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// The ranges of two sequences, of lengths `old_len` and `new_len`, around the index
+/// pairs of a common subsequence: the unpaired elements before each pair, and
+/// finally those after the last pair.
+fn segments(
+    pairs: Vec<(usize, usize)>,
+    old_len: usize,
+    new_len: usize,
+) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut segments = Vec::with_capacity(pairs.len() + 1);
+    let (mut old_start, mut new_start) = (0, 0);
+    for (i, j) in pairs {
+        segments.push((old_start..i, new_start..j));
+        (old_start, new_start) = (i + 1, j + 1);
+    }
+    segments.push((old_start..old_len, new_start..new_len));
+    segments
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> String {
+        let old: KeyDefinition = old.parse().expect("should parse");
+        let new: KeyDefinition = new.parse().expect("should parse");
+        old.diff(&new).to_string()
+    }
+
+    #[rstest]
+    #[case("ipsource", "ipsource", "")]
+    #[case("group:ipsource:\"a\"", "group:ipsource:a", "")]
+    #[case("ipsource", "ipsource,ipdestination", "added key 1: `ipdestination`")]
+    #[case("ipsource,ipdestination", "ipdestination", "removed key 0: `ipsource`")]
+    #[case(
+        "ipsource,ipdestination,ipprotocol",
+        "ipprotocol,ipsource,ipdestination",
+        "moved key 2 to 0: `ipprotocol`"
+    )]
+    #[case(
+        "ipsource,ipprotocol",
+        "ipdestination,ipprotocol",
+        "replaced key 0 `ipsource` with `ipdestination`"
+    )]
+    #[case(
+        "group:ipsource:a:b",
+        "group:ipsource:a:c",
+        "renamed group 0.2 `b` to `c`"
+    )]
+    #[case("group:ipsource:a", "group:ipsource:a:b", "added group 0.2 `b`")]
+    #[case("group:ipsource:a:b", "group:ipsource:b", "removed group 0.1 `a`")]
+    #[case(
+        "group:ipsource:a",
+        "group:ipdestination:a",
+        "replaced argument 0.0 `ipsource` with `ipdestination`"
+    )]
+    #[case(
+        "group:[country:ipsource]:a",
+        "group:[country:ipdestination]:a",
+        "replaced argument 0.0.0 `ipsource` with `ipdestination`"
+    )]
+    #[case(
+        "f:ipsource:1",
+        "f:ipsource:2:3",
+        "replaced argument 0.1 `1` with `2`\nadded argument 0.2: `3`"
+    )]
+    #[case(
+        "f:ipsource",
+        "g:ipsource",
+        "replaced key 0 `f:ipsource` with `g:ipsource`"
+    )]
+    #[case(
+        "country:ipsource",
+        "group:ipsource:a",
+        "replaced key 0 `country:ipsource` with `group:ipsource:a`"
+    )]
+    #[case(
+        "group:ipsource:a,ipdestination",
+        "ipdestination,group:ipsource:b",
+        "moved key 0 to 1: `group:ipsource:b`\nrenamed group 1.1 `a` to `b`"
+    )]
+    fn test_diff(#[case] old: &str, #[case] new: &str, #[case] expected: &str) {
+        assert_eq!(diff(old, new), expected);
+    }
+
+    #[test]
+    fn test_diff_paths() {
+        let old: KeyDefinition = "ipsource,group:ipdestination:a:b".parse().unwrap();
+        let new: KeyDefinition = "group:ipdestination:b".parse().unwrap();
+        let edits = old.diff(&new).into_edits();
+        assert_eq!(
+            edits,
+            vec![
+                KeyEdit::Removed {
+                    path: KeyPath::new(vec![0]),
+                    expression: KeyName::IpSource.into(),
+                },
+                KeyEdit::GroupRemoved {
+                    path: KeyPath::new(vec![0, 1]),
+                    name: "a".to_string(),
+                },
+            ]
+        );
+        assert!(edits[0].path().is_key());
+        assert!(!edits[1].path().is_key());
+    }
+
+    #[rstest]
+    #[case("a,b,c", "c,b,a")]
+    #[case("a,b,c,d", "d,a,e")]
+    #[case("group:[f:a:b]:x:y,c", "c,group:[f:b:a:c]:y")]
+    fn test_diff_is_empty_only_if_equal(#[case] old: &str, #[case] new: &str) {
+        let old: KeyDefinition = old.parse().unwrap();
+        let new: KeyDefinition = new.parse().unwrap();
+        assert!(old.diff(&old).is_empty());
+        assert!(!old.diff(&new).is_empty());
+        assert!(!new.diff(&old).is_empty());
+    }
+}
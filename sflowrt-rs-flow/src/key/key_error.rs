@@ -0,0 +1,489 @@
+//! Structured errors for the key definition DSL parser.
+//!
+//! [`KeyParseError`] is the error type the `nom` parsers in
+//! [`key_parser`](super::key_parser) produce; it borrows the input and records what
+//! the parser expected to find where it failed. [`KeySyntaxError`] is its owned
+//! counterpart, positioned by byte offset in the full input, which can render the
+//! input with a caret under the failure.
+//!
+//! [`KeyError`] is the error type of the crate as a whole: any of the errors its
+//! parsers, checkers and registries return, with syntax errors split up by what went
+//! wrong, for callers that need to tell failures apart:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{
+//!     key_error::KeyError,
+//!     key_parser::{parse_key_definition_complete_with, KEY_PARSEOPTS},
+//! };
+//!
+//! let opts = KEY_PARSEOPTS.with_strict(true);
+//! let error: KeyError = parse_key_definition_complete_with(&opts, "ipsorce")
+//!     .unwrap_err()
+//!     .into();
+//! assert!(matches!(error, KeyError::Unknown(_)));
+//! ```
+//!
+//! The parsers themselves return [`KeySyntaxError`], not [`KeyError`]: every way they
+//! can fail is a syntax error at a position in the input, which is what callers
+//! rendering errors for users need, and matching on a [`KeyError`] there could only
+//! ever find one of its syntax variants. Where a function returns [`KeyError`], `?`
+//! converts them.
+
+use std::{collections::BTreeSet, fmt};
+
+use nom::error::{ContextError, ErrorKind, ParseError};
+
+use super::key_lint::UnknownLintRule;
+use super::key_literal::LiteralKind;
+use super::key_parser::InvalidKeyParserOptions;
+use super::key_registry::RegistrationError;
+use super::key_signature::SignatureError;
+use super::key_type::TypeError;
+
+/// Something the parser expected to find, but didn't.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Expected {
+    /// A specific character, such as a separator or a bracket.
+    Char(char),
+    /// An identifier: a key name, key function name, or group name.
+    Identifier,
+    /// Key function arguments matching the function's signature.
+    ValidArguments,
+    /// A known key name; in strict mode, unknown key names are errors.
+    KnownKeyName,
+    /// A known key function; in strict mode, unknown key functions are errors.
+    KnownKeyFunction,
+    /// A literal of any kind.
+    AnyLiteral,
+    /// A well-formed literal of the given kind.
+    Literal(LiteralKind),
+    /// An escape sequence valid in a string literal.
+    EscapeSequence,
+    /// The end of the input.
+    EndOfInput,
+    /// Input within one of the parser's limits; see [`KeyParseLimit`].
+    Within(KeyParseLimit),
+    /// Anything else, described by the underlying `nom` error kind.
+    Other(String),
+}
+
+impl From<ErrorKind> for Expected {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::AlphaNumeric | ErrorKind::TakeWhile1 | ErrorKind::Tag => {
+                Expected::Identifier
+            }
+            ErrorKind::Verify => Expected::ValidArguments,
+            ErrorKind::Eof => Expected::EndOfInput,
+            _ => Expected::Other(kind.description().to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Char(c) => write!(f, "`{c}`"),
+            Expected::Identifier => write!(f, "a key or function name"),
+            Expected::ValidArguments => write!(f, "valid key function arguments"),
+            Expected::KnownKeyName => write!(f, "a known key name"),
+            Expected::KnownKeyFunction => write!(f, "a known key function"),
+            Expected::AnyLiteral => write!(f, "a literal"),
+            Expected::Literal(kind) => write!(f, "{}", kind.description_with_article()),
+            Expected::EscapeSequence => write!(f, "an escape sequence (`\\\"` or `\\\\`)"),
+            Expected::EndOfInput => write!(f, "end of input"),
+            Expected::Within(limit) => write!(f, "{limit}"),
+            Expected::Other(description) => write!(f, "{description}"),
+        }
+    }
+}
+
+/// A limit on the size of the input the parser accepts, which protects it (and its
+/// stack) from pathological input; see
+/// [`SflowRtKeyParserOptions`](super::key_parser::SflowRtKeyParserOptions).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeyParseLimit {
+    /// The maximum nesting depth of bracketed key function arguments.
+    Depth(usize),
+    /// The maximum number of key expressions in a key definition.
+    Expressions(usize),
+    /// The maximum length of the input, in bytes.
+    Length(usize),
+}
+
+impl fmt::Display for KeyParseLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyParseLimit::Depth(max) => write!(f, "at most {max} levels of nested key functions"),
+            KeyParseLimit::Expressions(max) => write!(f, "at most {max} key expressions"),
+            KeyParseLimit::Length(max) => write!(f, "at most {max} bytes of input"),
+        }
+    }
+}
+
+/// A key definition parser error, borrowing the remaining input at the point of
+/// failure.
+///
+/// When alternatives fail, the error from the one that got farthest is kept; if
+/// several got equally far, what each of them expected is merged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyParseError<'a> {
+    /// The remaining input at the point of failure.
+    pub remaining: &'a str,
+    /// What the parser expected to find at the point of failure.
+    pub expected: BTreeSet<Expected>,
+    /// What the parser was in the middle of parsing, innermost first.
+    pub context: Vec<&'static str>,
+}
+
+impl<'a> KeyParseError<'a> {
+    pub(crate) fn new(remaining: &'a str, expected: Expected) -> Self {
+        Self {
+            remaining,
+            expected: BTreeSet::from([expected]),
+            context: Vec::new(),
+        }
+    }
+
+    /// Position this error in `input`, the full input that was being parsed.
+    pub fn into_syntax_error(self, input: &str) -> KeySyntaxError {
+        let offset = input.len().saturating_sub(self.remaining.len());
+        self.into_syntax_error_at(input, offset)
+    }
+
+    /// Position this error in `input` at `offset`; for errors from parsing only a
+    /// part of `input`, whose remaining input isn't a suffix of `input`.
+    pub fn into_syntax_error_at(self, input: &str, offset: usize) -> KeySyntaxError {
+        KeySyntaxError {
+            input: input.to_string(),
+            offset,
+            expected: self.expected.into_iter().collect(),
+            context: self.context,
+        }
+    }
+}
+
+impl<'a> ParseError<&'a str> for KeyParseError<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        Self::new(input, kind.into())
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        // the inner error is more specific than the combinator that wraps it
+        other
+    }
+
+    fn from_char(input: &'a str, c: char) -> Self {
+        Self::new(input, Expected::Char(c))
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.remaining.len().cmp(&other.remaining.len()) {
+            // `other` got farther
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Equal => {
+                self.expected.extend(other.expected);
+                self
+            }
+        }
+    }
+}
+
+impl<'a> ContextError<&'a str> for KeyParseError<'a> {
+    fn add_context(_input: &'a str, ctx: &'static str, mut other: Self) -> Self {
+        other.context.push(ctx);
+        other
+    }
+}
+
+impl fmt::Display for KeyParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expected(f, &self.expected)?;
+        if self.remaining.is_empty() {
+            write!(f, ", found end of input")
+        } else {
+            write!(f, " at `{}`", self.remaining)
+        }
+    }
+}
+
+impl std::error::Error for KeyParseError<'_> {}
+
+/// A syntax error in a key definition, positioned in the input that was parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySyntaxError {
+    /// The full input that was being parsed.
+    pub input: String,
+    /// The byte offset of the failure in `input`.
+    pub offset: usize,
+    /// What the parser expected to find at `offset`.
+    pub expected: Vec<Expected>,
+    /// What the parser was in the middle of parsing, innermost first.
+    pub context: Vec<&'static str>,
+}
+
+impl KeySyntaxError {
+    /// Render the line of input containing the error, with a caret under the failure
+    /// and a description of what was expected, e.g.:
+    ///
+    /// ```text
+    /// ipsource,group:[country:ipsource
+    ///                                 ^ expected `]`
+    ///   (in key function argument, in key expression)
+    /// ```
+    pub fn render(&self) -> String {
+        let offset = self.offset.min(self.input.len());
+        let (before, after) = self.input.split_at(offset);
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = after.find('\n').map_or(self.input.len(), |i| offset + i);
+        let column = self.input[line_start..offset].chars().count();
+        let mut rendered = format!(
+            "{}\n{}^ {}",
+            &self.input[line_start..line_end],
+            " ".repeat(column),
+            self.message()
+        );
+        if !self.context.is_empty() {
+            let context: Vec<String> = self.context.iter().map(|c| format!("in {c}")).collect();
+            rendered.push_str(&format!("\n  ({})", context.join(", ")));
+        }
+        rendered
+    }
+
+    /// The limit the input exceeded, if that is why it failed to parse.
+    pub fn exceeded_limit(&self) -> Option<KeyParseLimit> {
+        self.expected.iter().find_map(|expected| match expected {
+            Expected::Within(limit) => Some(*limit),
+            _ => None,
+        })
+    }
+
+    fn message(&self) -> String {
+        let mut message = String::new();
+        let expected: BTreeSet<Expected> = self.expected.iter().cloned().collect();
+        // formatting into a `String` can't fail
+        let _ = write_expected(&mut message, &expected);
+        message
+    }
+}
+
+impl fmt::Display for KeySyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message(), self.offset)?;
+        if let Some(context) = self.context.first() {
+            write!(f, " (in {context})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for KeySyntaxError {}
+
+/// Any error from working with key definitions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyError {
+    /// The input isn't a well-formed key definition.
+    Syntax(KeySyntaxError),
+    /// In strict mode, the input names an unknown key or key function.
+    Unknown(KeySyntaxError),
+    /// The input exceeds one of the parser's limits.
+    LimitExceeded {
+        limit: KeyParseLimit,
+        error: KeySyntaxError,
+    },
+    /// A key function call doesn't match its function's signature.
+    Signature(SignatureError),
+    /// A key function argument's type isn't one the function accepts.
+    Type(TypeError),
+    /// The parser options are invalid.
+    InvalidOptions(InvalidKeyParserOptions),
+    /// A key function couldn't be registered.
+    Registration(RegistrationError),
+    /// A lint rule couldn't be configured.
+    UnknownLintRule(UnknownLintRule),
+}
+
+/// Writes the wrapped error's message; a [`KeyError`] adds nothing to it, so it is
+/// transparent to error reporters, and its [`source`](std::error::Error::source) is
+/// the wrapped error's.
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner())
+    }
+}
+
+impl std::error::Error for KeyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+impl KeyError {
+    /// The error this wraps.
+    fn inner(&self) -> &(dyn std::error::Error + 'static) {
+        match self {
+            KeyError::Syntax(error)
+            | KeyError::Unknown(error)
+            | KeyError::LimitExceeded { error, .. } => error,
+            KeyError::Signature(error) => error,
+            KeyError::Type(error) => error,
+            KeyError::InvalidOptions(error) => error,
+            KeyError::Registration(error) => error,
+            KeyError::UnknownLintRule(error) => error,
+        }
+    }
+}
+
+/// Classifies the syntax error by what the parser expected: within a limit, or a known
+/// name (in strict mode); anything else is a plain syntax error.
+impl From<KeySyntaxError> for KeyError {
+    fn from(error: KeySyntaxError) -> Self {
+        if let Some(limit) = error.exceeded_limit() {
+            return KeyError::LimitExceeded { limit, error };
+        }
+        let is_unknown = error.expected.iter().any(|expected| {
+            matches!(
+                expected,
+                Expected::KnownKeyName | Expected::KnownKeyFunction
+            )
+        });
+        if is_unknown {
+            KeyError::Unknown(error)
+        } else {
+            KeyError::Syntax(error)
+        }
+    }
+}
+
+impl From<SignatureError> for KeyError {
+    fn from(error: SignatureError) -> Self {
+        KeyError::Signature(error)
+    }
+}
+
+impl From<TypeError> for KeyError {
+    fn from(error: TypeError) -> Self {
+        KeyError::Type(error)
+    }
+}
+
+impl From<InvalidKeyParserOptions> for KeyError {
+    fn from(error: InvalidKeyParserOptions) -> Self {
+        KeyError::InvalidOptions(error)
+    }
+}
+
+impl From<RegistrationError> for KeyError {
+    fn from(error: RegistrationError) -> Self {
+        KeyError::Registration(error)
+    }
+}
+
+impl From<UnknownLintRule> for KeyError {
+    fn from(error: UnknownLintRule) -> Self {
+        KeyError::UnknownLintRule(error)
+    }
+}
+
+fn write_expected(f: &mut impl fmt::Write, expected: &BTreeSet<Expected>) -> fmt::Result {
+    let expected: Vec<String> = expected.iter().map(ToString::to_string).collect();
+    match expected.as_slice() {
+        [] => write!(f, "syntax error"),
+        [only] => write!(f, "expected {only}"),
+        [init @ .., last] => write!(f, "expected {} or {last}", init.join(", ")),
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::key_parser::{
+        parse_key_definition_complete_with, SflowRtKeyParserOptions, KEY_PARSEOPTS,
+    };
+
+    #[test]
+    fn test_or_keeps_farthest_and_merges_ties() {
+        let input = "abc";
+        let near = KeyParseError::new(&input[1..], Expected::Char(':'));
+        let far = KeyParseError::new(&input[2..], Expected::Char(']'));
+        assert_eq!(near.clone().or(far.clone()), far);
+        assert_eq!(far.clone().or(near.clone()), far);
+        let tie = KeyParseError::new(&input[2..], Expected::Identifier);
+        assert_eq!(
+            far.or(tie).expected,
+            BTreeSet::from([Expected::Char(']'), Expected::Identifier])
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let input = "ipsource,group:[country:ipsource";
+        let error = KeySyntaxError {
+            input: input.to_string(),
+            offset: input.len(),
+            expected: vec![Expected::Char(']')],
+            context: vec!["key function argument", "key expression"],
+        };
+        assert_eq!(
+            error.render(),
+            [
+                "ipsource,group:[country:ipsource",
+                "                                ^ expected `]`",
+                "  (in key function argument, in key expression)",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            error.to_string(),
+            "expected `]` at byte 32 (in key function argument)"
+        );
+    }
+
+    #[test]
+    fn test_render_multiline_and_multiple_expected() {
+        let error = KeySyntaxError {
+            input: "ipsource\nip6source;".to_string(),
+            offset: 18,
+            expected: vec![Expected::EndOfInput, Expected::Char(',')],
+            context: vec![],
+        };
+        assert_eq!(
+            error.render(),
+            "ip6source;\n         ^ expected `,` or end of input"
+        );
+    }
+
+    #[rstest::rstest]
+    #[case(KEY_PARSEOPTS, "ipsource,", "syntax")]
+    #[case(KEY_PARSEOPTS.with_strict(true), "ipsource,ipsorce", "unknown")]
+    #[case(KEY_PARSEOPTS.with_strict(true), "grop:ipsource:a", "unknown")]
+    #[case(KEY_PARSEOPTS.with_max_length(4), "ipsource", "limit")]
+    fn test_classify_syntax_errors(
+        #[case] opts: SflowRtKeyParserOptions,
+        #[case] input: &str,
+        #[case] expected: &str,
+    ) {
+        let error: KeyError = parse_key_definition_complete_with(&opts, input)
+            .expect_err("should fail to parse")
+            .into();
+        let kind = match &error {
+            KeyError::Syntax(_) => "syntax",
+            KeyError::Unknown(_) => "unknown",
+            KeyError::LimitExceeded { .. } => "limit",
+            _ => "other",
+        };
+        assert_eq!(kind, expected, "{error}");
+    }
+
+    #[test]
+    fn test_key_error_is_transparent() {
+        use std::error::Error;
+
+        let error = KeyError::from(RegistrationError::BuiltinFunction("group"));
+        assert_eq!(error.to_string(), "`group` is a built-in key function");
+        // error reporters print each error in the chain; the message must appear once
+        assert!(error.source().is_none());
+    }
+}
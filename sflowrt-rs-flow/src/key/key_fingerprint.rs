@@ -0,0 +1,135 @@
+//! Stable content fingerprints of key definitions.
+//!
+//! A [`KeyFingerprint`] identifies a key definition by its content: it is computed
+//! from the canonical DSL (the [`Display`](std::fmt::Display) form) of the
+//! definition's [normal form](super::key_normalize), so definitions that normalize to
+//! the same tree get the same fingerprint however they were written, and it never
+//! changes between runs, platforms or releases of this crate:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::KeyDefinition;
+//!
+//! let a: KeyDefinition = "group:IPSource:\"trusted\",f:10.0.0.0/8".parse().unwrap();
+//! let b: KeyDefinition = "group:ipsource:trusted,f:{10.0.0.0/8}".parse().unwrap();
+//! assert_eq!(a.fingerprint(), b.fingerprint());
+//! assert!(a.fingerprint().to_string().starts_with("v2:"));
+//! ```
+//!
+//! Each fingerprint records the version of the scheme that computed it. Should the
+//! canonical form or the hash ever change, the version changes with it, so stored
+//! fingerprints are never silently compared against ones computed differently.
+//!
+//! Key definitions also implement [`Hash`] and [`Ord`], for use in in-memory
+//! collections; neither is stable across releases, and the order is only meaningful
+//! as a total order.
+
+use std::fmt;
+
+use super::KeyDefinition;
+
+/// A stable content fingerprint of a key definition; see the [module docs](self).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyFingerprint {
+    /// The version of the fingerprint scheme; currently always
+    /// [`KeyFingerprint::VERSION`].
+    pub version: u32,
+    pub hash: u128,
+}
+
+impl KeyFingerprint {
+    /// The current version of the fingerprint scheme: the 128-bit FNV-1a hash of the
+    /// UTF-8 bytes of the normal form's canonical DSL. (Version 1 hashed the canonical
+    /// DSL of the definition as it was parsed.)
+    pub const VERSION: u32 = 2;
+}
+
+/// Writes the version and the hash in hexadecimal; e.g. `v2:` followed by 32 hex
+/// digits.
+impl fmt::Display for KeyFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}:{:032x}", self.version, self.hash)
+    }
+}
+
+impl KeyDefinition {
+    /// This key definition's content fingerprint; see [`super::key_fingerprint`].
+    pub fn fingerprint(&self) -> KeyFingerprint {
+        KeyFingerprint {
+            version: KeyFingerprint::VERSION,
+            hash: fnv1a_128(self.clone().normalize().to_string().as_bytes()),
+        }
+    }
+}
+
+/// The 128-bit FNV-1a hash of `bytes`. The `fnv` crate only implements the 64-bit
+/// variant, which is too short to key storage by.
+fn fnv1a_128(bytes: &[u8]) -> u128 {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use super::*;
+
+    fn fingerprint(input: &str) -> KeyFingerprint {
+        input
+            .parse::<KeyDefinition>()
+            .expect("should parse")
+            .fingerprint()
+    }
+
+    #[test]
+    fn test_fnv1a_128() {
+        // the published test vectors for the empty string and "a"
+        assert_eq!(fnv1a_128(b""), 0x6c62272e07bb014262b821756295c58d);
+        assert_eq!(fnv1a_128(b"a"), 0xd228cb696f1a8caf78912b704e4a8964);
+    }
+
+    /// Fingerprints are stored, so they must never change for a given version.
+    #[test]
+    fn test_fingerprint_is_stable() {
+        assert_eq!(
+            fingerprint("IPSource,group:[country:ip6source]:\"trusted\"").to_string(),
+            "v2:3449a4eb9f4997e16661bf8a9d49fb3f"
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("group:ipsource:\"trusted\"", "group:ipsource:trusted")]
+    #[case("f:10.0.0.0/8", "f:{10.0.0.0/8}")]
+    #[case("f:{FE80::0:0/10}", "f:{fe80::/10}")]
+    #[case("f:\"a\\\"b\"", "f:\"a\\\"b\"")]
+    #[case("IPSource,country:IP6SOURCE", "ipsource,country:ip6source")]
+    #[case("or:ipsource:[or:ip6source:ipsource]", "or:ipsource:ip6source")]
+    fn test_equivalent_definitions_match(#[case] a: &str, #[case] b: &str) {
+        assert_eq!(fingerprint(a), fingerprint(b));
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,ipdestination", "ipdestination,ipsource")]
+    #[case("group:ipsource:a:b", "group:ipsource:b:a")]
+    #[case("f:24", "f:\"24\"")]
+    #[case("f:/a/", "f:\"a\"")]
+    fn test_different_definitions_differ(#[case] a: &str, #[case] b: &str) {
+        assert_ne!(fingerprint(a), fingerprint(b));
+    }
+
+    #[test]
+    fn test_hash_and_ord() {
+        let definitions: Vec<KeyDefinition> = ["ipsource", "ip6source", "ipsource"]
+            .iter()
+            .map(|input| input.parse().expect("should parse"))
+            .collect();
+        let hashed: HashSet<&KeyDefinition> = definitions.iter().collect();
+        let ordered: BTreeSet<&KeyDefinition> = definitions.iter().collect();
+        assert_eq!(hashed.len(), 2);
+        assert_eq!(ordered.len(), 2);
+    }
+}
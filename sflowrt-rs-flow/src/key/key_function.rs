@@ -1,36 +1,173 @@
-use super::{KeyExpression, KeyFunction};
+use std::fmt;
 
-#[derive(Clone, Debug, PartialEq)]
+use super::key_lexer::is_identifier;
+use super::key_literal::Literal;
+use super::key_registry::registered_key_function;
+use super::key_signature::{KEY_FUNCTION_NAME_COUNTRY, KEY_FUNCTION_NAME_GROUP};
+use super::{KeyExpression, KeyFunction, KeyName};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct UnknownKeyFunction {
     pub function_name: String,
     pub args: Vec<KeyExpression>,
 }
 
+impl fmt::Display for UnknownKeyFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_call(f, &self.function_name, &self.args)
+    }
+}
+
 impl From<UnknownKeyFunction> for KeyFunction {
     fn from(value: UnknownKeyFunction) -> Self {
         Self::Unknown(value)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A call to a known key function that is only described by its signature: a built-in
+/// one such as `mask:` or `or:`, or a user-defined one, registered at runtime via
+/// [`register_key_function`](super::key_registry::register_key_function).
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CustomKeyFunction {
+    pub function_name: String,
+    pub args: Vec<KeyExpression>,
+}
+
+impl CustomKeyFunction {
+    /// Evaluate the call with its registered plugin, given the values of its
+    /// arguments. Returns `None` if the function is no longer registered, has no
+    /// evaluator, or produces no value for these arguments.
+    pub fn evaluate(&self, args: &[&str]) -> Option<String> {
+        registered_key_function(&self.function_name)?.evaluate(args)
+    }
+}
+
+impl fmt::Display for CustomKeyFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_call(f, &self.function_name, &self.args)
+    }
+}
+
+impl From<CustomKeyFunction> for KeyFunction {
+    fn from(value: CustomKeyFunction) -> Self {
+        Self::Custom(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GroupKeyFunction {
     pub key: Box<KeyExpression>,
     pub group_names: Vec<String>,
 }
 
+impl GroupKeyFunction {
+    pub fn new(
+        key: impl Into<KeyExpression>,
+        group_names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            key: Box::new(key.into()),
+            group_names: group_names.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Build a `group:` call from its arguments, as parsed: a key expression, then
+    /// the group names. Returns `None` if the arguments don't fit.
+    pub(crate) fn from_arguments(args: Vec<KeyExpression>) -> Option<Self> {
+        let mut args = args.into_iter();
+        let key = args.next()?;
+        let group_names = args
+            .map(|arg| match arg {
+                KeyExpression::KeyName(name) => Some(name.as_identifier().to_string()),
+                KeyExpression::Literal(Literal::String(name)) => Some(name),
+                KeyExpression::KeyFunction(_) | KeyExpression::Literal(_) => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            key: Box::new(key),
+            group_names,
+        })
+    }
+}
+
+impl fmt::Display for GroupKeyFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{KEY_FUNCTION_NAME_GROUP}:")?;
+        write_argument(f, &self.key)?;
+        for group_name in &self.group_names {
+            // a group name that wouldn't parse back as a bare identifier is quoted
+            let is_identifier =
+                is_identifier(group_name) && !group_name.chars().all(|c| c.is_ascii_digit());
+            if is_identifier {
+                write!(f, ":{group_name}")?;
+            } else {
+                write!(f, ":{}", Literal::String(group_name.clone()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl From<GroupKeyFunction> for KeyFunction {
     fn from(value: GroupKeyFunction) -> Self {
         Self::Group(value)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CountryKeyFunction {
     pub arg: String,
 }
 
+impl CountryKeyFunction {
+    pub fn new(key_name: impl Into<KeyName>) -> Self {
+        Self {
+            arg: key_name.into().as_identifier().to_string(),
+        }
+    }
+
+    /// Build a `country:` call from its arguments, as parsed: a single key name.
+    /// Returns `None` if the arguments don't fit.
+    pub(crate) fn from_arguments(args: Vec<KeyExpression>) -> Option<Self> {
+        match args.as_slice() {
+            [KeyExpression::KeyName(name)] => Some(Self {
+                arg: name.as_identifier().to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CountryKeyFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{KEY_FUNCTION_NAME_COUNTRY}:{}", self.arg)
+    }
+}
+
 impl From<CountryKeyFunction> for KeyFunction {
     fn from(value: CountryKeyFunction) -> Self {
         Self::Country(value)
     }
 }
+
+/// Write a key function call, `name:arg1:arg2:...`.
+fn write_call(
+    f: &mut fmt::Formatter<'_>,
+    function_name: &str,
+    args: &[KeyExpression],
+) -> fmt::Result {
+    write!(f, "{function_name}")?;
+    for arg in args {
+        write!(f, ":")?;
+        write_argument(f, arg)?;
+    }
+    Ok(())
+}
+
+/// Write a key function argument; a nested key function is bracketed.
+fn write_argument(f: &mut fmt::Formatter<'_>, arg: &KeyExpression) -> fmt::Result {
+    match arg {
+        KeyExpression::KeyFunction(function) => write!(f, "[{function}]"),
+        _ => write!(f, "{arg}"),
+    }
+}
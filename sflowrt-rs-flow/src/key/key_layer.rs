@@ -0,0 +1,214 @@
+//! The protocol layers a key definition needs decoded.
+//!
+//! Every known key is read from one [`ProtocolLayer`] of a sampled packet, at some
+//! encapsulation depth (0 for the outermost headers, 1 for the headers inside a
+//! tunnel, and so on); see [`KeyName::key_layer`]. A collector only needs to decode
+//! the layers that a key definition's keys are read from, and the layers those are
+//! carried in. [`KeyDefinition::protocol_layers`] finds them:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{key_layer::ProtocolLayer, KeyDefinition};
+//!
+//! let definition: KeyDefinition = "group:ip6source:a,country:ip6destination".parse().unwrap();
+//! let layers = definition.protocol_layers();
+//! assert!(layers.requires(ProtocolLayer::Ethernet));
+//! assert!(layers.requires(ProtocolLayer::Ipv6));
+//! assert!(!layers.requires(ProtocolLayer::Ipv4));
+//! assert!(layers.is_complete());
+//! ```
+//!
+//! The layers of unknown keys can't be known, so a key definition with any is
+//! incomplete: a collector has to decode everything it can for it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::key_visit::Visit;
+use super::{KeyDefinition, KeyName};
+
+/// A protocol layer of a sampled packet, that a collector decodes to read keys from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolLayer {
+    Ethernet,
+    Ipv4,
+    Ipv6,
+    // Add a layer here with the first known key name read from it
+}
+
+impl ProtocolLayer {
+    /// The layer this one is carried in, which has to be decoded to reach it.
+    pub fn carrier(&self) -> Option<ProtocolLayer> {
+        match self {
+            ProtocolLayer::Ethernet => None,
+            ProtocolLayer::Ipv4 | ProtocolLayer::Ipv6 => Some(ProtocolLayer::Ethernet),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolLayer::Ethernet => write!(f, "Ethernet"),
+            ProtocolLayer::Ipv4 => write!(f, "IPv4"),
+            ProtocolLayer::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// A protocol layer at an encapsulation depth: 0 for the outermost headers of a
+/// packet, 1 for the headers inside a tunnel, and so on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyLayer {
+    pub depth: usize,
+    pub layer: ProtocolLayer,
+}
+
+impl fmt::Display for KeyLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (depth {})", self.layer, self.depth)
+    }
+}
+
+impl KeyName {
+    /// The layer this key is read from, or `None` for an unknown key.
+    pub fn key_layer(&self) -> Option<KeyLayer> {
+        let layer = match self {
+            KeyName::IpSource | KeyName::IpDestination => ProtocolLayer::Ipv4,
+            KeyName::Ip6Offset
+            | KeyName::Ip6TOS
+            | KeyName::Ip6ECN
+            | KeyName::Ip6DSCP
+            | KeyName::Ip6DSCPName
+            | KeyName::Ip6FlowLabel
+            | KeyName::Ip6TTL
+            | KeyName::Ip6Source
+            | KeyName::Ip6Destination
+            | KeyName::Ip6Bytes
+            | KeyName::Ip6Extensions
+            | KeyName::Ip6FragmentOffset
+            | KeyName::Ip6FragmentMFlag
+            | KeyName::Ip6NextHeader => ProtocolLayer::Ipv6,
+            KeyName::Unknown(_) => return None,
+        };
+        // none of the known keys are of tunnelled headers (yet)
+        Some(KeyLayer { depth: 0, layer })
+    }
+}
+
+/// The protocol layers a key definition needs decoded, from
+/// [`KeyDefinition::protocol_layers`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerDependencies {
+    /// Every layer to decode, with the known keys read from it; a layer that's only
+    /// needed to reach another has none.
+    pub layers: BTreeMap<KeyLayer, BTreeSet<KeyName>>,
+    /// The unknown keys referenced, whose layers can't be known.
+    pub unknown_keys: BTreeSet<KeyName>,
+}
+
+impl LayerDependencies {
+    /// Whether `layer` has to be decoded, at any depth.
+    pub fn requires(&self, layer: ProtocolLayer) -> bool {
+        self.layers.keys().any(|key_layer| key_layer.layer == layer)
+    }
+
+    /// Whether every layer needed is known; that is, there are no unknown keys.
+    pub fn is_complete(&self) -> bool {
+        self.unknown_keys.is_empty()
+    }
+
+    fn add(&mut self, key_name: &KeyName) {
+        let Some(key_layer) = key_name.key_layer() else {
+            self.unknown_keys.insert(key_name.clone());
+            return;
+        };
+        self.layers
+            .entry(key_layer)
+            .or_default()
+            .insert(key_name.clone());
+        let mut carrier = key_layer.layer.carrier();
+        while let Some(layer) = carrier {
+            self.layers
+                .entry(KeyLayer {
+                    depth: key_layer.depth,
+                    layer,
+                })
+                .or_default();
+            carrier = layer.carrier();
+        }
+    }
+}
+
+impl Visit for LayerDependencies {
+    fn visit_key_name(&mut self, key_name: &KeyName) {
+        self.add(key_name);
+    }
+}
+
+impl KeyDefinition {
+    /// The protocol layers this key definition's keys are read from; see
+    /// [`super::key_layer`].
+    pub fn protocol_layers(&self) -> LayerDependencies {
+        let mut dependencies = LayerDependencies::default();
+        dependencies.visit_key_definition(self);
+        dependencies
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const ETHERNET: KeyLayer = KeyLayer {
+        depth: 0,
+        layer: ProtocolLayer::Ethernet,
+    };
+    const IPV4: KeyLayer = KeyLayer {
+        depth: 0,
+        layer: ProtocolLayer::Ipv4,
+    };
+    const IPV6: KeyLayer = KeyLayer {
+        depth: 0,
+        layer: ProtocolLayer::Ipv6,
+    };
+
+    #[rstest]
+    #[case("ipsource", &[(IPV4, &["ipsource"][..]), (ETHERNET, &[])], &[])]
+    #[case(
+        "ipsource,group:[country:ipdestination]:a",
+        &[(IPV4, &["ipsource", "ipdestination"][..]), (ETHERNET, &[])],
+        &[]
+    )]
+    #[case(
+        "or:ipsource:ip6source,ip6ttl",
+        &[(IPV4, &["ipsource"][..]), (IPV6, &["ip6source", "ip6ttl"]), (ETHERNET, &[])],
+        &[]
+    )]
+    #[case("f:[g:mykey]:24:\"s\"", &[], &["mykey"])]
+    #[case("ip6ttl,tcpsourceport", &[(IPV6, &["ip6ttl"][..]), (ETHERNET, &[])], &["tcpsourceport"])]
+    fn test_protocol_layers(
+        #[case] input: &str,
+        #[case] layers: &[(KeyLayer, &[&str])],
+        #[case] unknown_keys: &[&str],
+    ) {
+        let definition: KeyDefinition = input.parse().expect("should parse");
+        let expected = LayerDependencies {
+            layers: layers
+                .iter()
+                .map(|(layer, keys)| (*layer, keys.iter().map(|&key| key.into()).collect()))
+                .collect(),
+            unknown_keys: unknown_keys.iter().map(|&key| key.into()).collect(),
+        };
+        let dependencies = definition.protocol_layers();
+        assert_eq!(dependencies, expected);
+        assert_eq!(dependencies.is_complete(), unknown_keys.is_empty());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(IPV6.to_string(), "IPv6 (depth 0)");
+    }
+}
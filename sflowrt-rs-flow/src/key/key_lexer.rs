@@ -0,0 +1,285 @@
+//! The lexical layer of the key definition DSL: the rules for the identifiers and
+//! literals that [`key_parser`](super::key_parser) reads key definitions with; see the
+//! [grammar](super::key_parser#grammar).
+//!
+//! There is no separate tokenizing pass. The parser calls these lexers as it goes,
+//! since what a token can be depends on where it is: a bare `24` is an integer literal
+//! as a key function argument, but an (unknown) key name as a key expression. Key
+//! names, key function names and group names are all identifiers, with the same rule:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::key_lexer::is_identifier;
+//!
+//! assert!(is_identifier("ip6_offset"));
+//! assert!(is_identifier("grüppe_1"));
+//! assert!(!is_identifier("a-b"));
+//! ```
+
+use std::borrow::Cow;
+use std::net::{IpAddr, Ipv4Addr};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{take_while, take_while1},
+    character::complete::{char, digit1, satisfy},
+    combinator::{map, not, opt, recognize},
+    sequence::{pair, terminated, tuple},
+};
+
+use super::key_borrowed::BorrowedLiteral;
+use super::key_error::{Expected, KeyParseError};
+use super::key_literal::{AddressLiteral, LiteralKind};
+use super::key_parser::KeyParseResult;
+
+/// The characters that delimit or appear in literals, other than alphanumerics; no
+/// separator may be one of these.
+pub(crate) const LITERAL_CHARACTERS: [char; 7] = ['"', '\\', '/', '{', '}', '.', '-'];
+
+/// Whether `c` can appear in an identifier: any alphanumeric character (including
+/// non-ASCII ones), or `_`. Key names, key function names and group names all follow
+/// this rule.
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether all of `s` lexes as a single identifier.
+pub fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_identifier_char)
+}
+
+/// Lex an identifier.
+pub(crate) fn identifier(input: &str) -> KeyParseResult<'_, &str> {
+    take_while1(is_identifier_char)(input)
+}
+
+// literals ///////////////////////////////////////////////////////////////////////////
+
+/// Lex a literal, borrowing its string or regular expression from the input where
+/// possible.
+///
+/// Once a literal has clearly started (e.g. after an opening `"`, or digits followed by
+/// a `.`), a malformed literal is a [`nom::Err::Failure`], rather than being read as
+/// something else.
+pub(crate) fn literal(input: &str) -> KeyParseResult<'_, BorrowedLiteral<'_>> {
+    // what each kind of literal would have started with is just noise in an error
+    // message, so if none of them started, report that a literal was expected
+    alt((
+        map(lex_string_literal, BorrowedLiteral::String),
+        map(lex_regex_literal, BorrowedLiteral::Regex),
+        map(lex_braced_address_literal, BorrowedLiteral::Address),
+        map(lex_bare_address_literal, BorrowedLiteral::Address),
+        map(lex_integer_literal, BorrowedLiteral::Integer),
+    ))(input)
+    .map_err(|e| match e {
+        nom::Err::Error(_) => nom::Err::Error(KeyParseError::new(input, Expected::AnyLiteral)),
+        e => e,
+    })
+}
+
+fn literal_failure(input: &str, expected: Expected) -> nom::Err<KeyParseError<'_>> {
+    nom::Err::Failure(KeyParseError::new(input, expected))
+}
+
+/// Succeeds if the input doesn't continue with a character that would make the
+/// preceding literal part of an identifier (or a longer literal).
+fn end_of_bare_literal(input: &str) -> KeyParseResult<'_, ()> {
+    not(satisfy(|c: char| is_identifier_char(c) || c == '.'))(input)
+}
+
+/// Lex a double quoted string literal. `\"` and `\\` are the only escapes. The value
+/// is borrowed from the input unless it has escapes.
+fn lex_string_literal(input: &str) -> KeyParseResult<'_, Cow<'_, str>> {
+    let (body, _) = char('"')(input)?;
+    // the unescaped value, once an escape means it can't just be borrowed
+    let mut value: Option<String> = None;
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let value = value.map_or(Cow::Borrowed(&body[..i]), Cow::Owned);
+                return Ok((&body[i + 1..], value));
+            }
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\'))) => value
+                    .get_or_insert_with(|| body[..i].to_string())
+                    .push(escaped),
+                _ => return Err(literal_failure(&body[i..], Expected::EscapeSequence)),
+            },
+            _ => {
+                if let Some(value) = &mut value {
+                    value.push(c);
+                }
+            }
+        }
+    }
+    Err(literal_failure(&body[body.len()..], Expected::Char('"')))
+}
+
+/// Lex a `/` delimited regular expression literal. `\/` is an escaped `/`; other
+/// escapes are kept as they are, for the regular expression itself to interpret. The
+/// pattern is borrowed from the input unless it has escaped `/`s.
+fn lex_regex_literal(input: &str) -> KeyParseResult<'_, Cow<'_, str>> {
+    let (body, _) = char('/')(input)?;
+    // the unescaped pattern, once an escaped `/` means it can't just be borrowed
+    let mut pattern: Option<String> = None;
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => {
+                let pattern = pattern.map_or(Cow::Borrowed(&body[..i]), Cow::Owned);
+                return Ok((&body[i + 1..], pattern));
+            }
+            '\\' => match chars.next() {
+                Some((_, '/')) => pattern
+                    .get_or_insert_with(|| body[..i].to_string())
+                    .push('/'),
+                Some((_, escaped)) => {
+                    if let Some(pattern) = &mut pattern {
+                        pattern.push('\\');
+                        pattern.push(escaped);
+                    }
+                }
+                None => {
+                    if let Some(pattern) = &mut pattern {
+                        pattern.push('\\');
+                    }
+                }
+            },
+            _ => {
+                if let Some(pattern) = &mut pattern {
+                    pattern.push(c);
+                }
+            }
+        }
+    }
+    Err(literal_failure(&body[body.len()..], Expected::Char('/')))
+}
+
+/// Lex an IPv4 or IPv6 address or prefix in braces, e.g. `{fe80::/10}`.
+fn lex_braced_address_literal(input: &str) -> KeyParseResult<'_, AddressLiteral> {
+    let (body, _) = char('{')(input)?;
+    let (rest, text) =
+        take_while(|c: char| c.is_ascii_hexdigit() || matches!(c, ':' | '.' | '/'))(body)?;
+    let address = parse_address(text)
+        .ok_or_else(|| literal_failure(body, Expected::Literal(LiteralKind::Address)))?;
+    let (rest, _) = char::<_, KeyParseError>('}')(rest)
+        .map_err(|_| literal_failure(rest, Expected::Char('}')))?;
+    Ok((rest, address))
+}
+
+/// Lex a bare IPv4 address or prefix, e.g. `10.0.0.0/8`.
+fn lex_bare_address_literal(input: &str) -> KeyParseResult<'_, AddressLiteral> {
+    let (rest, text) = recognize(tuple((
+        digit1,
+        char('.'),
+        take_while(|c: char| c.is_ascii_digit() || c == '.'),
+        opt(pair(char('/'), digit1)),
+    )))(input)?;
+    let address = parse_address(text)
+        .filter(|address| address.address.is_ipv4())
+        .ok_or_else(|| literal_failure(input, Expected::Literal(LiteralKind::Address)))?;
+    let (rest, _) = end_of_bare_literal(rest)
+        .map_err(|_| literal_failure(input, Expected::Literal(LiteralKind::Address)))?;
+    Ok((rest, address))
+}
+
+fn parse_address(text: &str) -> Option<AddressLiteral> {
+    let (address, prefix_len) = match text.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len.parse::<u8>().ok()?)),
+        None => (text, None),
+    };
+    // `Ipv4Addr` is strict about its format, but `IpAddr` would also accept IPv6
+    // addresses with IPv4 suffixes, so only fall back to it for IPv6
+    let address = match address.parse::<Ipv4Addr>() {
+        Ok(address) => IpAddr::V4(address),
+        Err(_) => IpAddr::V6(address.parse().ok()?),
+    };
+    let address = AddressLiteral {
+        address,
+        prefix_len,
+    };
+    match prefix_len {
+        Some(prefix_len) if prefix_len > address.max_prefix_len() => None,
+        _ => Some(address),
+    }
+}
+
+/// Lex a decimal integer literal, e.g. `24` or `-1`.
+fn lex_integer_literal(input: &str) -> KeyParseResult<'_, i64> {
+    let (rest, text) =
+        terminated(recognize(pair(opt(char('-')), digit1)), end_of_bare_literal)(input)?;
+    let value = text
+        .parse()
+        .map_err(|_| literal_failure(input, Expected::Literal(LiteralKind::Integer)))?;
+    Ok((rest, value))
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use nom::Finish;
+
+    use super::*;
+
+    #[rstest::rstest]
+    #[case("ipsource", true)]
+    #[case("ip6_offset", true)]
+    #[case("grüppe_1", true)]
+    #[case("_", true)]
+    #[case("24", true)]
+    #[case("", false)]
+    #[case("a-b", false)]
+    #[case("a b", false)]
+    #[case("a:b", false)]
+    fn test_is_identifier(#[case] input: &str, #[case] expected: bool) {
+        assert_eq!(is_identifier(input), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("\"a \\\"b\":x", BorrowedLiteral::String(Cow::Owned("a \"b".to_string())), ":x")]
+    #[case("\"ab\"", BorrowedLiteral::String(Cow::Borrowed("ab")), "")]
+    #[case("24:x", BorrowedLiteral::Integer(24), ":x")]
+    #[case("-1]", BorrowedLiteral::Integer(-1), "]")]
+    #[case("/a\\/b/", BorrowedLiteral::Regex(Cow::Owned("a/b".to_string())), "")]
+    #[case(
+        "10.0.0.0/8,",
+        BorrowedLiteral::Address(AddressLiteral {
+            address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+            prefix_len: Some(8),
+        }),
+        ","
+    )]
+    #[case(
+        "{fe80::/10}",
+        BorrowedLiteral::Address(AddressLiteral {
+            address: "fe80::".parse().expect("should be an address"),
+            prefix_len: Some(10),
+        }),
+        ""
+    )]
+    fn test_literal(
+        #[case] input: &str,
+        #[case] expected: BorrowedLiteral,
+        #[case] expected_rest: &str,
+    ) {
+        assert_eq!(literal(input), Ok((expected_rest, expected)));
+    }
+
+    #[rstest::rstest]
+    // an identifier, not an integer
+    #[case("24ab", 0, &[Expected::AnyLiteral])]
+    #[case("\"a", 2, &[Expected::Char('"')])]
+    #[case("10.0.0.300", 0, &[Expected::Literal(LiteralKind::Address)])]
+    fn test_literal_errors(
+        #[case] input: &str,
+        #[case] offset: usize,
+        #[case] expected: &[Expected],
+    ) {
+        let error = literal(input)
+            .finish()
+            .expect_err("should fail to lex")
+            .into_syntax_error(input);
+        assert_eq!(error.offset, offset, "{}", error.render());
+        assert_eq!(error.expected, expected, "{}", error.render());
+    }
+}
@@ -0,0 +1,539 @@
+//! Lints for key definitions: definitions that parse and type check, but are probably
+//! not what was meant.
+//!
+//! A [`Linter`] runs a set of [`LintRule`]s over a [`KeyDefinition`], each at its own
+//! [`Severity`]. [`Linter::default`] has every built-in rule at its default severity;
+//! a rule's severity can be changed by name, and [`Severity::Allow`] turns it off:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{
+//!     key_lint::{Linter, Severity},
+//!     KeyDefinition,
+//! };
+//!
+//! let definition: KeyDefinition = "ipsource,ip6ttl,ipsource".parse().unwrap();
+//! let linter = Linter::default()
+//!     .with_severity("duplicate-key", Severity::Allow)
+//!     .unwrap();
+//! let lints: Vec<String> = linter
+//!     .lint(&definition)
+//!     .iter()
+//!     .map(ToString::to_string)
+//!     .collect();
+//! assert_eq!(
+//!     lints,
+//!     vec![
+//!         "error[mixed-address-families]: `ip6ttl` (IPv6) and `ipsource` (IPv4) are \
+//!          never in the same packet; combine them with `or:` instead"
+//!     ]
+//! );
+//! ```
+//!
+//! The built-in rules are [`DuplicateKey`], [`SingleGroup`], [`MixedAddressFamilies`]
+//! and [`HighCardinality`]; other rules can be added with [`Linter::with_rule`].
+
+use std::{fmt, str::FromStr};
+
+use super::key_function::{CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction};
+use super::key_signature::KEY_FUNCTION_NAME_OR;
+use super::key_visit::{self, Visit};
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// How much a lint matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The rule is turned off; its findings aren't reported.
+    Allow,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self {
+            Severity::Allow => "allow",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}")
+    }
+}
+
+/// Parses `allow`, `warn` (or `warning`), and `deny` (or `error`).
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Severity::Allow),
+            "warn" | "warning" => Ok(Severity::Warning),
+            "deny" | "error" => Ok(Severity::Error),
+            _ => Err(format!("unknown lint severity `{s}`")),
+        }
+    }
+}
+
+/// Something a [`LintRule`] found in a key definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintFinding {
+    /// The index of the key expression the finding is about, or `None` if it is about
+    /// the key definition as a whole.
+    pub key_index: Option<usize>,
+    pub message: String,
+}
+
+/// A check over key definitions. Rules are identified by their
+/// [`name`](LintRule::name), which is how their severity is configured.
+pub trait LintRule: Send + Sync {
+    /// The rule's name, in kebab-case; e.g. `duplicate-key`.
+    fn name(&self) -> &'static str;
+
+    /// A one-line description of what the rule finds.
+    fn description(&self) -> &'static str;
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, definition: &KeyDefinition) -> Vec<LintFinding>;
+}
+
+/// A [`LintFinding`] reported by a [`Linter`], with the rule that found it and the
+/// rule's severity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub key_index: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.rule, self.message)
+    }
+}
+
+/// An attempt to configure a lint rule that the [`Linter`] doesn't have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownLintRule(pub String);
+
+impl fmt::Display for UnknownLintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown lint rule `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownLintRule {}
+
+/// A set of lint rules, each with its configured severity.
+pub struct Linter {
+    rules: Vec<(Box<dyn LintRule>, Severity)>,
+}
+
+/// Every built-in rule, at its default severity.
+impl Default for Linter {
+    fn default() -> Self {
+        Self::empty()
+            .with_rule(DuplicateKey)
+            .with_rule(SingleGroup)
+            .with_rule(MixedAddressFamilies)
+            .with_rule(HighCardinality::default())
+    }
+}
+
+impl Linter {
+    /// A linter with no rules.
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Add a rule at its default severity, replacing any rule with the same name.
+    pub fn with_rule(mut self, rule: impl LintRule + 'static) -> Self {
+        let severity = rule.default_severity();
+        self.rules
+            .retain(|(existing, _)| existing.name() != rule.name());
+        self.rules.push((Box::new(rule), severity));
+        self
+    }
+
+    /// Set the severity of the rule named `name`.
+    pub fn with_severity(
+        mut self,
+        name: &str,
+        severity: Severity,
+    ) -> Result<Self, UnknownLintRule> {
+        match self.rules.iter_mut().find(|(rule, _)| rule.name() == name) {
+            Some((_, configured)) => *configured = severity,
+            None => return Err(UnknownLintRule(name.to_string())),
+        }
+        Ok(self)
+    }
+
+    /// The rules, in the order they run, with their configured severities.
+    pub fn rules(&self) -> impl Iterator<Item = (&dyn LintRule, Severity)> {
+        self.rules
+            .iter()
+            .map(|(rule, severity)| (rule.as_ref(), *severity))
+    }
+
+    /// Run every rule that isn't [allowed](Severity::Allow) over a key definition.
+    /// Lints are grouped by rule, in the order the rules run.
+    pub fn lint(&self, definition: &KeyDefinition) -> Vec<Lint> {
+        self.rules
+            .iter()
+            .filter(|(_, severity)| *severity != Severity::Allow)
+            .flat_map(|(rule, severity)| {
+                rule.check(definition).into_iter().map(|finding| Lint {
+                    rule: rule.name(),
+                    severity: *severity,
+                    key_index: finding.key_index,
+                    message: finding.message,
+                })
+            })
+            .collect()
+    }
+}
+
+// rules //////////////////////////////////////////////////////////////////////////////
+
+/// `duplicate-key`: a key expression that appears more than once. Flows are bucketed
+/// by the whole tuple, so the repeat adds nothing.
+pub struct DuplicateKey;
+
+impl LintRule for DuplicateKey {
+    fn name(&self) -> &'static str {
+        "duplicate-key"
+    }
+
+    fn description(&self) -> &'static str {
+        "a key expression that appears more than once"
+    }
+
+    fn check(&self, definition: &KeyDefinition) -> Vec<LintFinding> {
+        let keys = definition.keys();
+        keys.iter()
+            .enumerate()
+            .filter_map(|(index, key)| {
+                let first = keys[..index].iter().position(|earlier| earlier == key)?;
+                Some(LintFinding {
+                    key_index: Some(index),
+                    message: format!("`{key}` duplicates key {}", first + 1),
+                })
+            })
+            .collect()
+    }
+}
+
+/// `single-group`: a `group:` call with a single group name. Every value is then
+/// either in that group or in none, which is better expressed as a flow filter.
+pub struct SingleGroup;
+
+impl LintRule for SingleGroup {
+    fn name(&self) -> &'static str {
+        "single-group"
+    }
+
+    fn description(&self) -> &'static str {
+        "a `group:` call with a single group name"
+    }
+
+    fn check(&self, definition: &KeyDefinition) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        for (index, key) in definition.iter().enumerate() {
+            let mut finder = SingleGroupFinder::default();
+            finder.visit_key_expression(key);
+            findings.extend(finder.groups.into_iter().map(|group| LintFinding {
+                key_index: Some(index),
+                message: format!(
+                    "`{group}` has a single group name; consider filtering on the group \
+                     instead"
+                ),
+            }));
+        }
+        findings
+    }
+}
+
+#[derive(Default)]
+struct SingleGroupFinder {
+    groups: Vec<String>,
+}
+
+impl Visit for SingleGroupFinder {
+    fn visit_group_key_function(&mut self, group: &GroupKeyFunction) {
+        if group.group_names.len() == 1 {
+            self.groups
+                .push(KeyFunction::Group(group.clone()).to_string());
+        }
+        key_visit::visit_group_key_function(self, group);
+    }
+}
+
+/// An IP address family; every known key is only present in packets of one family.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    fn of(key_name: &KeyName) -> Option<Self> {
+        match key_name {
+            KeyName::IpSource | KeyName::IpDestination => Some(AddressFamily::Ipv4),
+            KeyName::Unknown(_) => None,
+            _ => Some(AddressFamily::Ipv6),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::Ipv4 => write!(f, "IPv4"),
+            AddressFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// `mixed-address-families`: IPv4 and IPv6 keys in the same key definition. A packet
+/// is either IPv4 or IPv6, so no flow has values for both; keys combined with `or:`
+/// are alternatives, and don't count.
+pub struct MixedAddressFamilies;
+
+impl LintRule for MixedAddressFamilies {
+    fn name(&self) -> &'static str {
+        "mixed-address-families"
+    }
+
+    fn description(&self) -> &'static str {
+        "IPv4 and IPv6 keys that can never both be present in a flow"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, definition: &KeyDefinition) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        let mut first: Option<(KeyName, AddressFamily)> = None;
+        for (index, key) in definition.iter().enumerate() {
+            let mut finder = AddressFamilyFinder::default();
+            finder.visit_key_expression(key);
+            for (key_name, family) in finder.keys {
+                match &first {
+                    None => first = Some((key_name, family)),
+                    Some((first_name, first_family)) if *first_family != family => {
+                        findings.push(LintFinding {
+                            key_index: Some(index),
+                            message: format!(
+                                "`{key_name}` ({family}) and `{first_name}` ({first_family}) \
+                                 are never in the same packet; combine them with `or:` instead"
+                            ),
+                        });
+                        // one finding per key expression is enough
+                        break;
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// The keys in a key expression, and their address families, outside of any `or:`.
+#[derive(Default)]
+struct AddressFamilyFinder {
+    keys: Vec<(KeyName, AddressFamily)>,
+}
+
+impl Visit for AddressFamilyFinder {
+    fn visit_key_name(&mut self, key_name: &KeyName) {
+        if let Some(family) = AddressFamily::of(key_name) {
+            self.keys.push((key_name.clone(), family));
+        }
+    }
+
+    fn visit_custom_key_function(&mut self, custom: &CustomKeyFunction) {
+        if custom.function_name != KEY_FUNCTION_NAME_OR {
+            key_visit::visit_custom_key_function(self, custom);
+        }
+    }
+
+    fn visit_unknown_key_function(&mut self, unknown: &UnknownKeyFunction) {
+        if unknown.function_name != KEY_FUNCTION_NAME_OR {
+            key_visit::visit_unknown_key_function(self, unknown);
+        }
+    }
+}
+
+/// `high-cardinality`: more than [`max_keys`](HighCardinality::max_keys) keys with
+/// (nearly) unbounded value sets, such as addresses, used directly as keys. Every
+/// combination of their values is a separate flow, so such definitions track a very
+/// large number of flows. Keys used as arguments to key functions (e.g.
+/// `country:ipsource`) don't count.
+pub struct HighCardinality {
+    pub max_keys: usize,
+}
+
+impl Default for HighCardinality {
+    fn default() -> Self {
+        Self { max_keys: 2 }
+    }
+}
+
+impl HighCardinality {
+    /// Whether a key has at least a million or so values; see
+    /// [`KeyName::cardinality_bits`].
+    fn is_high_cardinality(key_name: &KeyName) -> bool {
+        key_name.cardinality_bits().is_some_and(|bits| bits >= 20.0)
+    }
+}
+
+impl LintRule for HighCardinality {
+    fn name(&self) -> &'static str {
+        "high-cardinality"
+    }
+
+    fn description(&self) -> &'static str {
+        "too many keys with (nearly) unbounded value sets"
+    }
+
+    fn check(&self, definition: &KeyDefinition) -> Vec<LintFinding> {
+        let keys: Vec<String> = definition
+            .iter()
+            .filter_map(|key| match key {
+                KeyExpression::KeyName(key_name) if Self::is_high_cardinality(key_name) => {
+                    Some(format!("`{key_name}`"))
+                }
+                _ => None,
+            })
+            .collect();
+        if keys.len() <= self.max_keys {
+            return Vec::new();
+        }
+        vec![LintFinding {
+            key_index: None,
+            message: format!(
+                "{} high-cardinality keys ({}); at most {} are recommended",
+                keys.len(),
+                keys.join(", "),
+                self.max_keys
+            ),
+        }]
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(linter: &Linter, input: &str) -> Vec<(&'static str, Option<usize>, String)> {
+        let definition: KeyDefinition = input.parse().expect("should parse");
+        linter
+            .lint(&definition)
+            .into_iter()
+            .map(|lint| (lint.rule, lint.key_index, lint.message))
+            .collect()
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,ipdestination,group:ipsource:a:b", &[])]
+    #[case(
+        "ipsource,group:ipdestination:a:b,ipsource",
+        &[("duplicate-key", Some(2), "`ipsource` duplicates key 1")]
+    )]
+    #[case(
+        "ip6source,group:[country:ip6source]:internal,f:[group:ip6ttl:a]",
+        &[
+            (
+                "single-group",
+                Some(1),
+                "`group:[country:ip6source]:internal` has a single group name; consider \
+                 filtering on the group instead"
+            ),
+            (
+                "single-group",
+                Some(2),
+                "`group:ip6ttl:a` has a single group name; consider filtering on the group \
+                 instead"
+            ),
+        ]
+    )]
+    #[case(
+        "ipsource,ip6ttl,country:ip6destination",
+        &[
+            (
+                "mixed-address-families",
+                Some(1),
+                "`ip6ttl` (IPv6) and `ipsource` (IPv4) are never in the same packet; \
+                 combine them with `or:` instead"
+            ),
+            (
+                "mixed-address-families",
+                Some(2),
+                "`ip6destination` (IPv6) and `ipsource` (IPv4) are never in the same \
+                 packet; combine them with `or:` instead"
+            ),
+        ]
+    )]
+    #[case("or:ipsource:ip6source,or:ipdestination:ip6destination,ip6ttl", &[])]
+    #[case(
+        "ip6source,ip6destination,ip6flowlabel,country:ip6source",
+        &[(
+            "high-cardinality",
+            None,
+            "3 high-cardinality keys (`ip6source`, `ip6destination`, `ip6flowlabel`); at \
+             most 2 are recommended"
+        )]
+    )]
+    fn test_default_linter(#[case] input: &str, #[case] expected: &[(&str, Option<usize>, &str)]) {
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(rule, key_index, message)| (*rule, *key_index, message.to_string()))
+            .collect();
+        assert_eq!(lint(&Linter::default(), input), expected);
+    }
+
+    #[test]
+    fn test_configure_linter() {
+        let input = "ipsource,ipdestination,ipsource,ip6ttl";
+        let linter = Linter::default()
+            .with_severity("mixed-address-families", Severity::Allow)
+            .expect("should be a rule")
+            .with_severity("duplicate-key", Severity::Error)
+            .expect("should be a rule")
+            .with_rule(HighCardinality { max_keys: 1 });
+        let definition: KeyDefinition = input.parse().expect("should parse");
+        let lints: Vec<_> = linter
+            .lint(&definition)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            lints,
+            vec![
+                "error[duplicate-key]: `ipsource` duplicates key 1",
+                "warning[high-cardinality]: 3 high-cardinality keys (`ipsource`, \
+                 `ipdestination`, `ipsource`); at most 1 are recommended",
+            ]
+        );
+        assert_eq!(
+            Linter::default()
+                .with_severity("duplicate-keys", Severity::Allow)
+                .err(),
+            Some(UnknownLintRule("duplicate-keys".to_string()))
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("allow", Ok(Severity::Allow))]
+    #[case("warn", Ok(Severity::Warning))]
+    #[case("error", Ok(Severity::Error))]
+    #[case("fatal", Err("unknown lint severity `fatal`".to_string()))]
+    fn test_parse_severity(#[case] input: &str, #[case] expected: Result<Severity, String>) {
+        assert_eq!(input.parse::<Severity>(), expected);
+    }
+}
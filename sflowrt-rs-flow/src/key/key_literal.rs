@@ -0,0 +1,127 @@
+//! Literal arguments to key functions: integers, strings, IP addresses and prefixes,
+//! and regular expressions.
+//!
+//! # Syntax
+//!
+//! | Literal | Syntax | Examples |
+//! |---------|--------|----------|
+//! | integer | optional `-`, then decimal digits | `24`, `-1` |
+//! | string  | double quoted; `\"` and `\\` are the only escapes | `"a,b"`, `"say \"hi\""` |
+//! | address | IPv4 or IPv6 address, optionally with a `/` prefix length, in braces | `{10.0.0.0/8}`, `{fe80::/10}` |
+//! | regex   | delimited by `/`; `\/` is a literal `/`, other escapes are kept as-is | `/^10\.0\./` |
+//!
+//! IPv4 addresses and prefixes may also be written bare (`10.0.0.0/8`); IPv6 ones must
+//! be braced, since `:` separates key function arguments.
+
+use std::{fmt, net::IpAddr};
+
+/// A literal argument to a key function.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Literal {
+    Integer(i64),
+    String(String),
+    Address(AddressLiteral),
+    Regex(String),
+}
+
+/// An IP address, or an IP prefix if it has a prefix length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AddressLiteral {
+    pub address: IpAddr,
+    pub prefix_len: Option<u8>,
+}
+
+impl AddressLiteral {
+    /// The maximum prefix length for this literal's address family.
+    pub fn max_prefix_len(&self) -> u8 {
+        match self.address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+}
+
+/// The kind of a [`Literal`], without its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LiteralKind {
+    Integer,
+    String,
+    Address,
+    Regex,
+}
+
+impl Literal {
+    pub fn kind(&self) -> LiteralKind {
+        match self {
+            Literal::Integer(_) => LiteralKind::Integer,
+            Literal::String(_) => LiteralKind::String,
+            Literal::Address(_) => LiteralKind::Address,
+            Literal::Regex(_) => LiteralKind::Regex,
+        }
+    }
+}
+
+impl LiteralKind {
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            LiteralKind::Integer => "integer",
+            LiteralKind::String => "string",
+            LiteralKind::Address => "IP address or prefix",
+            LiteralKind::Regex => "regular expression",
+        }
+    }
+
+    /// The description, with an indefinite article; e.g. "an integer".
+    pub(crate) fn description_with_article(&self) -> &'static str {
+        match self {
+            LiteralKind::Integer => "an integer",
+            LiteralKind::String => "a string",
+            LiteralKind::Address => "an IP address or prefix",
+            LiteralKind::Regex => "a regular expression",
+        }
+    }
+}
+
+/// Writes the literal in its canonical DSL form, quoting and escaping as needed. IP
+/// addresses are always braced.
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Integer(value) => write!(f, "{value}"),
+            Literal::String(value) => {
+                let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, "\"{escaped}\"")
+            }
+            Literal::Address(address) => write!(f, "{{{address}}}"),
+            Literal::Regex(pattern) => write!(f, "/{}/", pattern.replace('/', "\\/")),
+        }
+    }
+}
+
+impl fmt::Display for AddressLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(prefix_len) = self.prefix_len {
+            write!(f, "/{prefix_len}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<i64> for Literal {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<AddressLiteral> for Literal {
+    fn from(value: AddressLiteral) -> Self {
+        Self::Address(value)
+    }
+}
@@ -0,0 +1,251 @@
+//! Normalization of key definitions.
+//!
+//! Different key definitions can bucket flows identically. The passes here are
+//! [`Fold`]s that each rewrite one kind of equivalent form into a single normal form,
+//! and [`KeyDefinition::normalize`] runs these, in order:
+//!
+//! 1. [`LowercaseKeyNames`]: sFlow-RT's key names are case-insensitive, so
+//!    `IPSource` is `ipsource`.
+//! 2. [`FlattenOr`]: `or:` takes the first of its alternatives that has a value, so
+//!    `or:a:[or:b:c]` is `or:a:b:c`.
+//! 3. [`CollapseRedundant`]: an `or:` alternative that repeats an earlier one is never
+//!    used, so `or:a:b:a` is `or:a:b`; and `or:a` is just `a`.
+//!
+//! A pass that rewrites a call's arguments types the call afresh, as the parser would
+//! type it; e.g. `country:[or:ipsource]` is `country:ipsource`, a `country:` call.
+//! None of them changes the flow keys a definition produces. [`DedupKeys`] goes
+//! further: a key expression that repeats an earlier one doesn't change how flows are
+//! bucketed, so `ipsource,ipsource` buckets flows as `ipsource` does; but dropping it
+//! changes how many values each flow key has, and where they are. So it isn't part of
+//! the normal form, and callers that only care about the bucketing run it themselves.
+//!
+//! ```
+//! use sflowrt_rs_flow::key::KeyDefinition;
+//!
+//! let definition: KeyDefinition =
+//!     "or:IPSource:[or:ip6source:ipsource],ipsource".parse().unwrap();
+//! assert_eq!(definition.normalize().to_string(), "or:ipsource:ip6source,ipsource");
+//! ```
+//!
+//! Normalizing is idempotent, so two key definitions are equivalent in these ways
+//! exactly when their normal forms are equal (and have equal
+//! [fingerprints](super::key_fingerprint)).
+
+use super::key_signature::KEY_FUNCTION_NAME_OR;
+use super::key_visit::{self, Fold};
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+impl KeyDefinition {
+    /// This key definition in normal form; see [`super::key_normalize`]. It has as
+    /// many key expressions as this one, in the same order.
+    pub fn normalize(self) -> KeyDefinition {
+        let definition = LowercaseKeyNames.fold_key_definition(self);
+        let definition = FlattenOr.fold_key_definition(definition);
+        CollapseRedundant.fold_key_definition(definition)
+    }
+}
+
+/// Rewrites built-in key names written in another case to the key they name; e.g.
+/// `IPSource` to `ipsource`. Other unknown key names are left as they are.
+pub struct LowercaseKeyNames;
+
+impl Fold for LowercaseKeyNames {
+    fn fold_key_name(&mut self, key_name: KeyName) -> KeyName {
+        match key_name {
+            KeyName::Unknown(name) => KeyName::from_sflowrt_key_name(&name.to_lowercase())
+                .unwrap_or(KeyName::Unknown(name)),
+            key_name => key_name,
+        }
+    }
+}
+
+/// Splices the alternatives of an `or:` that is itself an alternative of an `or:` into
+/// the outer one; e.g. `or:a:[or:b:c]:d` to `or:a:b:c:d`.
+pub struct FlattenOr;
+
+impl Fold for FlattenOr {
+    fn fold_key_expression(&mut self, expression: KeyExpression) -> KeyExpression {
+        let mut expression = key_visit::fold_key_expression(self, expression);
+        if let Some(alternatives) = or_alternatives_mut(&mut expression) {
+            // the alternatives were folded first, so they're flat already
+            *alternatives = std::mem::take(alternatives)
+                .into_iter()
+                .flat_map(
+                    |mut alternative| match or_alternatives_mut(&mut alternative) {
+                        Some(nested) => std::mem::take(nested),
+                        None => vec![alternative],
+                    },
+                )
+                .collect();
+        }
+        retyped(expression)
+    }
+}
+
+/// Drops `or:` alternatives that repeat an earlier one, and replaces an `or:` of a
+/// single alternative with that alternative; e.g. `or:a:b:a` to `or:a:b`, and
+/// `or:a:a` to `a`.
+pub struct CollapseRedundant;
+
+impl Fold for CollapseRedundant {
+    fn fold_key_expression(&mut self, expression: KeyExpression) -> KeyExpression {
+        let mut expression = key_visit::fold_key_expression(self, expression);
+        if let Some(alternatives) = or_alternatives_mut(&mut expression) {
+            dedup(alternatives);
+            if alternatives.len() == 1 {
+                return alternatives.remove(0);
+            }
+        }
+        retyped(expression)
+    }
+}
+
+/// Drops key expressions that repeat an earlier one; e.g. `ipsource,ipdestination,
+/// ipsource` to `ipsource,ipdestination`. This changes the arity of the flow's key,
+/// and the positions of its values, but not which flows share one; so unlike the other
+/// passes, [`KeyDefinition::normalize`] doesn't run it.
+pub struct DedupKeys;
+
+impl Fold for DedupKeys {
+    fn fold_key_definition(&mut self, definition: KeyDefinition) -> KeyDefinition {
+        let mut definition = key_visit::fold_key_definition(self, definition);
+        dedup(&mut definition.keys);
+        definition
+    }
+}
+
+/// `expression`, with a call whose arguments may have been rewritten typed as
+/// [`KeyFunction::from_call`] types it; e.g. `country:` of an `or:` of a single key
+/// isn't a `country:` call, but `country:` of that key is.
+fn retyped(expression: KeyExpression) -> KeyExpression {
+    match expression {
+        KeyExpression::KeyFunction(KeyFunction::Custom(custom)) => {
+            KeyFunction::from_call(custom.function_name, custom.args).into()
+        }
+        KeyExpression::KeyFunction(KeyFunction::Unknown(unknown)) => {
+            KeyFunction::from_call(unknown.function_name, unknown.args).into()
+        }
+        expression => expression,
+    }
+}
+
+/// The arguments of an `or:` call (whether it matches `or:`'s signature or not).
+fn or_alternatives_mut(expression: &mut KeyExpression) -> Option<&mut Vec<KeyExpression>> {
+    match expression {
+        KeyExpression::KeyFunction(KeyFunction::Custom(custom))
+            if custom.function_name == KEY_FUNCTION_NAME_OR =>
+        {
+            Some(&mut custom.args)
+        }
+        KeyExpression::KeyFunction(KeyFunction::Unknown(unknown))
+            if unknown.function_name == KEY_FUNCTION_NAME_OR =>
+        {
+            Some(&mut unknown.args)
+        }
+        _ => None,
+    }
+}
+
+/// Drop the expressions that repeat an earlier one, keeping the order of the rest.
+fn dedup(expressions: &mut Vec<KeyExpression>) {
+    // these lists are short, so a quadratic search is fine
+    let mut unique: Vec<KeyExpression> = Vec::with_capacity(expressions.len());
+    for expression in std::mem::take(expressions) {
+        if !unique.contains(&expression) {
+            unique.push(expression);
+        }
+    }
+    *expressions = unique;
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn definition(input: &str) -> KeyDefinition {
+        input.parse().expect("should parse")
+    }
+
+    #[rstest]
+    #[case("ipsource,group:ipsource:a", "ipsource,group:ipsource:a")]
+    // LowercaseKeyNames
+    #[case("IPSource,country:IP6SOURCE", "ipsource,country:ip6source")]
+    #[case("MyKey,group:Ip6Ttl:Trusted", "MyKey,group:ip6ttl:Trusted")]
+    // FlattenOr
+    #[case("or:a:[or:b:[or:c:d]]:e", "or:a:b:c:d:e")]
+    #[case("f:[or:a:b]:[or:c:d]", "f:[or:a:b]:[or:c:d]")]
+    #[case("or:[f:[or:a:b]]:c", "or:[f:[or:a:b]]:c")]
+    // CollapseRedundant
+    #[case("or:a:b:a", "or:a:b")]
+    #[case("or:ipsource:ipsource", "ipsource")]
+    #[case("group:[or:[or:x]]:a", "group:x:a")]
+    #[case("or:a:[or:b:a]", "or:a:b")]
+    #[case("country:[or:ipsource]", "country:ipsource")]
+    #[case("or:a:[or:b:24]", "or:a:b:24")]
+    // repeated keys are kept
+    #[case("ipsource,IPSOURCE,or:ipsource", "ipsource,ipsource,ipsource")]
+    fn test_normalize(#[case] input: &str, #[case] expected: &str) {
+        let normalized = definition(input).normalize();
+        assert_eq!(normalized.to_string(), expected);
+        assert_eq!(
+            normalized,
+            definition(expected),
+            "should be typed as parsed"
+        );
+        assert_eq!(
+            normalized.clone().normalize(),
+            normalized,
+            "should be idempotent"
+        );
+    }
+
+    #[rstest]
+    #[case("ipsource,ipdestination,ipsource", "ipsource,ipdestination")]
+    #[case("ipsource,IPSOURCE,or:ipsource", "ipsource")]
+    #[case(
+        "group:ipsource:a,group:ipsource:b",
+        "group:ipsource:a,group:ipsource:b"
+    )]
+    fn test_dedup_normalized_keys(#[case] input: &str, #[case] expected: &str) {
+        let deduped = DedupKeys.fold_key_definition(definition(input).normalize());
+        assert_eq!(deduped.to_string(), expected);
+    }
+
+    #[test]
+    fn test_passes() {
+        let input = "or:IPSOURCE:[or:ipsource],ipsource";
+        assert_eq!(
+            LowercaseKeyNames
+                .fold_key_definition(definition(input))
+                .to_string(),
+            "or:ipsource:[or:ipsource],ipsource"
+        );
+        assert_eq!(
+            FlattenOr.fold_key_definition(definition(input)).to_string(),
+            "or:IPSOURCE:ipsource,ipsource"
+        );
+        assert_eq!(
+            CollapseRedundant
+                .fold_key_definition(definition(input))
+                .to_string(),
+            "or:IPSOURCE:ipsource,ipsource"
+        );
+        assert_eq!(
+            DedupKeys.fold_key_definition(definition(input)).to_string(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_normalize_equivalent_fingerprints() {
+        let a = definition("or:ipsource:[or:ip6source:ipsource]").normalize();
+        let b = definition("OR:IPSOURCE:IP6SOURCE").normalize();
+        // only key names are case-insensitive
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        let c = definition("or:IPSOURCE:[or:IP6SOURCE:ipsource]").normalize();
+        assert_eq!(a.fingerprint(), c.fingerprint());
+    }
+}
@@ -1,49 +1,355 @@
 //! sFlow-RT Key definition DSL parser.
-//! See: https://sflow-rt.com/define_flow.php
+//! See: <https://sflow-rt.com/define_flow.php>
+//!
+//! # Grammar
+//!
+//! In ISO EBNF, with sFlow-RT's separators; other dialects replace `","`, `":"`, `"["`
+//! and `"]"` with the separators in their [`SflowRtKeyParserOptions`]. The lexical
+//! rules (everything from `identifier` down) are implemented by
+//! [`key_lexer`](super::key_lexer).
+//!
+//! ```text
+//! key definition     = key expression , { "," , key expression } ;
+//! key expression     = key function call | key name ;
+//! key function call  = function name , ":" , argument , { ":" , argument } ;
+//! argument           = "[" , key function call , "]" | literal | identifier ;
+//! key name           = identifier ;
+//! function name      = identifier ;
+//!
+//! identifier         = identifier char , { identifier char } ;
+//! identifier char    = ? any Unicode alphanumeric character ? | "_" ;
+//! literal            = string | regex | address | integer ;
+//! string             = '"' , { string char | '\"' | '\\' } , '"' ;
+//! string char        = ? any character except '"' and '\' ? ;
+//! regex              = "/" , { regex char | "\" , ? any character ? } , "/" ;
+//! regex char         = ? any character except "/" and "\" ? ;
+//! address            = "{" , address text , "}" | bare address ;
+//! address text       = ? an IPv4 or IPv6 address, optionally followed by "/" and a
+//!                        prefix length ? ;
+//! bare address       = digits , "." , { digit | "." } , [ "/" , digits ] ;
+//! integer            = [ "-" ] , digits ;
+//! digits             = digit , { digit } ;
+//! digit              = "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" ;
+//! ```
+//!
+//! Where the grammar is ambiguous:
+//!
+//! - a key function argument that is both a literal and an identifier (e.g. `24`) is a
+//!   literal;
+//! - a bare address or an integer can't be directly followed by an identifier
+//!   character or a `.`; `24ab` is an identifier, and `10.0.0.1a` is a malformed
+//!   address;
+//! - a bare address must be a well-formed IPv4 address or prefix, and a braced one an
+//!   IPv4 or IPv6 address or prefix.
+//!
+//! Which arguments a key function takes, and whether an identifier argument is a key
+//! name or something else (e.g. a group name), is up to the function's
+//! [signature](super::key_signature), not the grammar.
 
-use super::key_function::{CountryKeyFunction, GroupKeyFunction, UnknownKeyFunction};
+use super::key_borrowed::{
+    BorrowedKeyDefinition, BorrowedKeyExpression, BorrowedKeyFunction, BorrowedKeyName,
+    BorrowedLiteral,
+};
+use super::key_error::{Expected, KeyError, KeyParseError, KeyParseLimit, KeySyntaxError};
+use super::key_lexer::{identifier, is_identifier_char, literal, LITERAL_CHARACTERS};
+use super::key_literal::Literal;
+use super::key_signature::{key_function_signature, type_call, ArgumentKind};
+use super::key_span::{
+    KeyFunctionKind, Span, SpannedKeyDefinition, SpannedKeyExpression, SpannedKeyFunction,
+};
 use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName, KEY_NAME_TO_VARIANT};
 
-use anyhow::Context;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while1},
-    character::complete::{alphanumeric1, char},
-    combinator::{map, peek},
-    multi::{many0, many1, separated_list1},
-    sequence::{delimited, preceded, terminated},
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{map, not, peek},
+    error::{context, ContextError, ParseError},
+    multi::{many1, separated_list1},
+    sequence::{pair, preceded, terminated},
     Finish, IResult,
 };
 
-pub(crate) struct SflowRtKeyParserOptions {
-    pub(crate) key_def_sep: char,
-    pub(crate) fn_arg_sep: char,
-    pub(crate) fn_nest_open: char,
-    pub(crate) fn_nest_close: char,
+/// The result type of the key definition parsers.
+pub type KeyParseResult<'a, T> = IResult<&'a str, T, KeyParseError<'a>>;
+
+/// The characters that separate and nest the parts of a key definition, and how
+/// strictly it is parsed.
+///
+/// The default, [`KEY_PARSEOPTS`], is sFlow-RT's own syntax; e.g.
+/// `ipsource,group:[country:ip6source]:trusted`. Other dialects can change any of the
+/// separators, for example to embed key definitions in systems that reserve commas:
+///
+/// ```
+/// use sflowrt_rs_flow::key::key_parser::{parse_key_definition_with, KEY_PARSEOPTS};
+///
+/// let opts = KEY_PARSEOPTS.with_key_def_sep(';');
+/// let (rest, definition) = parse_key_definition_with(&opts, "ipsource;ip6source").unwrap();
+/// assert_eq!(rest, "");
+/// ```
+///
+/// By default, parsing is lenient: unknown key names and key function calls are parsed
+/// as [`KeyName::Unknown`] and [`KeyFunction::Unknown`]. In strict mode, they are parse
+/// errors instead, positioned at the unknown name:
+///
+/// ```
+/// use sflowrt_rs_flow::key::key_parser::{parse_key_definition_complete_with, KEY_PARSEOPTS};
+///
+/// let opts = KEY_PARSEOPTS.with_strict(true);
+/// let error = parse_key_definition_complete_with(&opts, "ipsource,ipsorce").unwrap_err();
+/// assert_eq!(error.offset, 9);
+/// assert_eq!(error.to_string(), "expected a known key name at byte 9");
+/// ```
+///
+/// Input that is too long, has too many key expressions, or nests key functions too
+/// deeply is rejected with an error naming the [limit](KeyParseLimit) it exceeded,
+/// positioned where it was exceeded; the default limits are far beyond what any real
+/// key definition needs:
+///
+/// ```
+/// use sflowrt_rs_flow::key::{
+///     key_error::KeyParseLimit,
+///     key_parser::{parse_key_definition_complete_with, KEY_PARSEOPTS},
+/// };
+///
+/// let opts = KEY_PARSEOPTS.with_max_depth(1);
+/// let error = parse_key_definition_complete_with(&opts, "f:[g:[h:ipsource]]").unwrap_err();
+/// assert_eq!(error.exceeded_limit(), Some(KeyParseLimit::Depth(1)));
+/// assert_eq!(error.offset, 5);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SflowRtKeyParserOptions {
+    /// Separates the key expressions of a key definition. sFlow-RT: `,`.
+    pub key_def_sep: char,
+    /// Separates a key function's name and arguments. sFlow-RT: `:`.
+    pub fn_arg_sep: char,
+    /// Opens a key function nested as an argument of another. sFlow-RT: `[`.
+    pub fn_nest_open: char,
+    /// Closes a key function nested as an argument of another. sFlow-RT: `]`.
+    pub fn_nest_close: char,
+    /// Whether an unknown key name or key function call is a parse error. A call to a
+    /// known key function whose arguments don't match its signature counts as an
+    /// unknown call. Default: `false`.
+    pub strict: bool,
+    /// The maximum nesting depth of bracketed key function arguments; e.g.
+    /// `group:[country:ipsource]:a` has a depth of 1. Default: 32.
+    pub max_depth: usize,
+    /// The maximum number of key expressions in a key definition, not counting key
+    /// function arguments. Default: 256.
+    pub max_expressions: usize,
+    /// The maximum length of the input, in bytes. Default: 16 KiB.
+    pub max_length: usize,
+    /// The nesting depth of the key function argument being parsed; only ever
+    /// non-zero in the options [`parse_nested`] passes down.
+    depth: usize,
 }
 
-pub(crate) const KEY_PARSEOPTS: SflowRtKeyParserOptions = SflowRtKeyParserOptions {
+/// sFlow-RT's own key definition syntax.
+pub const KEY_PARSEOPTS: SflowRtKeyParserOptions = SflowRtKeyParserOptions {
     key_def_sep: ',',
     fn_arg_sep: ':',
     fn_nest_open: '[',
     fn_nest_close: ']',
+    strict: false,
+    max_depth: 32,
+    max_expressions: 256,
+    max_length: 16 * 1024,
+    depth: 0,
 };
 
+impl Default for SflowRtKeyParserOptions {
+    fn default() -> Self {
+        KEY_PARSEOPTS
+    }
+}
+
+impl SflowRtKeyParserOptions {
+    pub const fn with_key_def_sep(mut self, key_def_sep: char) -> Self {
+        self.key_def_sep = key_def_sep;
+        self
+    }
+
+    pub const fn with_fn_arg_sep(mut self, fn_arg_sep: char) -> Self {
+        self.fn_arg_sep = fn_arg_sep;
+        self
+    }
+
+    pub const fn with_fn_nest(mut self, fn_nest_open: char, fn_nest_close: char) -> Self {
+        self.fn_nest_open = fn_nest_open;
+        self.fn_nest_close = fn_nest_close;
+        self
+    }
+
+    pub const fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub const fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub const fn with_max_expressions(mut self, max_expressions: usize) -> Self {
+        self.max_expressions = max_expressions;
+        self
+    }
+
+    pub const fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Check that these options describe an unambiguous syntax: the separators must
+    /// all differ, and none may be a character that can appear in an identifier or
+    /// that delimits a [literal](super::key_literal). The parsers assume (but don't
+    /// check) that their options are valid.
+    pub fn validate(&self) -> Result<(), InvalidKeyParserOptions> {
+        let separators = [
+            self.key_def_sep,
+            self.fn_arg_sep,
+            self.fn_nest_open,
+            self.fn_nest_close,
+        ];
+        for (i, &c) in separators.iter().enumerate() {
+            if is_identifier_char(c) {
+                return Err(InvalidKeyParserOptions::IdentifierCharacter(c));
+            }
+            if LITERAL_CHARACTERS.contains(&c) {
+                return Err(InvalidKeyParserOptions::LiteralCharacter(c));
+            }
+            if separators[..i].contains(&c) {
+                return Err(InvalidKeyParserOptions::DuplicateSeparator(c));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a [`SflowRtKeyParserOptions`] is invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidKeyParserOptions {
+    /// A separator is a character that can appear in an identifier.
+    IdentifierCharacter(char),
+    /// A separator is a character that delimits or appears in a literal.
+    LiteralCharacter(char),
+    /// The same character is used for more than one separator.
+    DuplicateSeparator(char),
+}
+
+impl std::fmt::Display for InvalidKeyParserOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidKeyParserOptions::IdentifierCharacter(c) => {
+                write!(f, "separator `{c}` can appear in an identifier")
+            }
+            InvalidKeyParserOptions::LiteralCharacter(c) => {
+                write!(f, "separator `{c}` can appear in a literal")
+            }
+            InvalidKeyParserOptions::DuplicateSeparator(c) => {
+                write!(f, "`{c}` is used for more than one separator")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidKeyParserOptions {}
+
 // parser: general purpose
 
 #[allow(dead_code)]
-fn parse_noop(input: &str) -> IResult<&str, &str> {
+fn parse_noop(input: &str) -> KeyParseResult<'_, &str> {
     Ok((input, ""))
 }
 
-fn alphanumeric1_or_underscore(input: &str) -> IResult<&str, &str> {
-    take_while1(|c: char| c.is_alphanumeric() || c == '_')(input)
+/// An unrecoverable parse error at `input`, for an unknown name (or a call that
+/// doesn't match its function's signature) in strict mode.
+fn strict_failure(input: &str, expected: Expected) -> nom::Err<KeyParseError<'_>> {
+    nom::Err::Failure(KeyParseError::new(input, expected))
+}
+
+/// An unrecoverable parse error at `input`, for input that exceeds one of the parser's
+/// limits.
+fn limit_failure(input: &str, limit: KeyParseLimit) -> nom::Err<KeyParseError<'_>> {
+    nom::Err::Failure(KeyParseError::new(input, Expected::Within(limit)))
+}
+
+/// Fail if `input`, the full input to a key definition parser, is longer than
+/// `opts.max_length`. The error is positioned at the first character past the limit.
+fn check_length<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> Result<(), nom::Err<KeyParseError<'a>>> {
+    if input.len() <= opts.max_length {
+        return Ok(());
+    }
+    let mut end = opts.max_length;
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    Err(limit_failure(
+        &input[end..],
+        KeyParseLimit::Length(opts.max_length),
+    ))
+}
+
+/// Parse a bracketed key function argument with `parse`, failing if that nests key
+/// functions deeper than `opts.max_depth`. Every recursive parser nests through here,
+/// which bounds how deep the parsers recurse.
+fn parse_nested<'a, O>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+    mut parse: impl FnMut(&SflowRtKeyParserOptions, &'a str) -> KeyParseResult<'a, O>,
+) -> KeyParseResult<'a, O> {
+    let (inner, _) = char(opts.fn_nest_open)(input)?;
+    if opts.depth >= opts.max_depth {
+        return Err(limit_failure(input, KeyParseLimit::Depth(opts.max_depth)));
+    }
+    let nested = SflowRtKeyParserOptions {
+        depth: opts.depth + 1,
+        ..opts.clone()
+    };
+    let (rest, output) = parse(&nested, inner)?;
+    let (rest, _) = char(opts.fn_nest_close)(rest)?;
+    Ok((rest, output))
+}
+
+/// Parse the key expressions of a key definition, separated by `opts.key_def_sep`,
+/// with `parse`. Like [`separated_list1`], this stops before a separator that isn't
+/// followed by a key expression; unlike it, it fails if there are more than
+/// `opts.max_expressions` key expressions or the input is longer than
+/// `opts.max_length`.
+fn parse_key_expressions<'a, O>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+    mut parse: impl FnMut(&'a str) -> KeyParseResult<'a, O>,
+) -> KeyParseResult<'a, Vec<O>> {
+    check_length(opts, input)?;
+    let (mut rest, first) = parse(input)?;
+    let mut keys = vec![first];
+    while let Ok((after_separator, _)) = char::<_, KeyParseError>(opts.key_def_sep)(rest) {
+        if keys.len() >= opts.max_expressions {
+            return Err(limit_failure(
+                after_separator,
+                KeyParseLimit::Expressions(opts.max_expressions),
+            ));
+        }
+        match parse(after_separator) {
+            Ok((after_key, key)) => {
+                keys.push(key);
+                rest = after_key;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((rest, keys))
 }
 
 // parser: key name
 
-fn parse_key_name_or_unknown(input: &str) -> IResult<&str, KeyName> {
-    let (input, key_name) = alphanumeric1(input)?;
+fn parse_key_name_or_unknown(input: &str) -> KeyParseResult<'_, KeyName> {
+    let (input, key_name) = identifier(input)?;
     let key_name = KEY_NAME_TO_VARIANT
         .get(key_name)
         .cloned()
@@ -55,8 +361,8 @@ fn parse_key_name_or_unknown(input: &str) -> IResult<&str, KeyName> {
 /// `Some`) only if it is able to recognize a key name from a known list. This
 /// function guarantees that if it returns `Some(key_name)`, then `key_name` is *not*
 /// a value of the `KeyName::Unknown` variant.
-fn _parse_key_name_known(input: &str) -> IResult<&str, Option<KeyName>> {
-    let (input, key_name_str) = peek(alphanumeric1)(input)?;
+fn _parse_key_name_known(input: &str) -> KeyParseResult<'_, Option<KeyName>> {
+    let (input, key_name_str) = peek(identifier)(input)?;
     if let Some(key_name) = KEY_NAME_TO_VARIANT.get(key_name_str) {
         let (input, _) = tag(key_name_str)(input)?;
         return Ok((input, Some((*key_name).clone())));
@@ -64,164 +370,610 @@ fn _parse_key_name_known(input: &str) -> IResult<&str, Option<KeyName>> {
     Ok((input, None))
 }
 
+// parser: literals
+
+/// Parse a literal key function argument; see [`super::key_literal`] for the syntax.
+///
+/// Once a literal has clearly started (e.g. after an opening `"`, or digits followed by
+/// a `.`), a malformed literal is a [`nom::Err::Failure`], rather than being read as
+/// something else.
+fn parse_literal(input: &str) -> KeyParseResult<'_, Literal> {
+    map(literal, BorrowedLiteral::into_owned)(input)
+}
+
 // parser: key functions
 
 fn _parse_key_function_name_from_separator<'a>(
     separator: char,
-) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+) -> impl FnMut(&'a str) -> KeyParseResult<'a, &'a str> {
     terminated(parse_key_function_name, char(separator))
 }
 
-fn parse_key_function_name(input: &str) -> IResult<&str, &str> {
-    alphanumeric1(input)
+fn parse_key_function_name(input: &str) -> KeyParseResult<'_, &str> {
+    identifier(input)
 }
 
-fn parse_key_function_argument(input: &str) -> IResult<&str, KeyExpression> {
-    alt((
-        delimited(
-            char(KEY_PARSEOPTS.fn_nest_open),
-            map(parse_key_function, KeyExpression::KeyFunction),
-            char(KEY_PARSEOPTS.fn_nest_close),
-        ),
-        map(parse_key_name_or_unknown, KeyExpression::KeyName),
-    ))(input)
+fn parse_key_function_argument<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, KeyExpression> {
+    context(
+        "key function argument",
+        alt((
+            |i| {
+                parse_nested(opts, i, |opts, i| {
+                    map(|i| parse_key_function(opts, i), KeyExpression::KeyFunction)(i)
+                })
+            },
+            map(parse_literal, KeyExpression::Literal),
+            map(
+                parse_key_function_identifier_argument,
+                KeyExpression::KeyName,
+            ),
+        )),
+    )(input)
 }
 
-fn parse_key_function_arguments(
-    input: &str,
+/// Parse a bare identifier argument to a key function. This is usually a key name,
+/// but may also be another kind of identifier (e.g. a group name); the function's
+/// signature determines how it is read. Identifiers may contain underscores.
+fn parse_key_function_identifier_argument(input: &str) -> KeyParseResult<'_, KeyName> {
+    let (input, identifier) = identifier(input)?;
+    let key_name = KEY_NAME_TO_VARIANT
+        .get(identifier)
+        .cloned()
+        .unwrap_or_else(|| KeyName::Unknown(identifier.to_string()));
+    Ok((input, key_name))
+}
+
+/// Parse a key function's arguments, each with the input it was parsed from.
+fn parse_key_function_arguments<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
     leading_separator: bool,
-) -> IResult<&str, Vec<KeyExpression>> {
+) -> KeyParseResult<'a, Vec<(&'a str, KeyExpression)>> {
+    let argument = |i| {
+        let (rest, arg) = parse_key_function_argument(opts, i)?;
+        Ok((rest, (i, arg)))
+    };
     if leading_separator {
-        many1(preceded(
-            char(KEY_PARSEOPTS.fn_arg_sep),
-            parse_key_function_argument,
-        ))(input)
+        many1(preceded(char(opts.fn_arg_sep), argument))(input)
     } else {
-        separated_list1(char(KEY_PARSEOPTS.fn_arg_sep), parse_key_function_argument)(input)
+        separated_list1(char(opts.fn_arg_sep), argument)(input)
     }
 }
 
-fn parse_key_function(input: &str) -> IResult<&str, KeyFunction> {
-    // Here, we require that each key function sub-combinator must parse its own
-    // function name. but, since we need to know which function name is starting in
-    // order to dispatch, we use `peek()`.
-    //
-    // Note that the `_parse_key_function_name_from_separator` approach is how we
-    // determine if this input is the start of a key function call at all. This
-    // *requires* that key functions have at least one argument; otherwise, it's
-    // probably just an unrecognized flow key name.
-    let (input, function_name) = peek(_parse_key_function_name_from_separator(
-        KEY_PARSEOPTS.fn_arg_sep,
-    ))(input)?;
-    // Again, the remaining input starts with the function name! We only peeked above.
-    match function_name {
-        "group" => {
-            let (input, kf) =
-                GroupKeyFunction::parse_key_function(input, KEY_PARSEOPTS.fn_arg_sep)?;
-            Ok((input, kf.into()))
-        }
-        "country" => {
-            let (input, kf) =
-                CountryKeyFunction::parse_key_function(input, KEY_PARSEOPTS.fn_arg_sep)?;
-            Ok((input, kf.into()))
-        }
-        _ => {
-            let (input, kf) =
-                UnknownKeyFunction::parse_key_function(input, KEY_PARSEOPTS.fn_arg_sep)?;
-            Ok((input, kf.into()))
+/// Parse a key function call's name and arguments, without interpreting them. In
+/// strict mode, an unknown key name where the function expects a key is an error.
+fn parse_key_function_call<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, (&'a str, Vec<KeyExpression>)> {
+    let (input, function_name) = _parse_key_function_name_from_separator(opts.fn_arg_sep)(input)?;
+    let (input, args) = parse_key_function_arguments(opts, input, false)?;
+    if opts.strict {
+        let unknown_key_names = args.iter().map(|(i, arg)| {
+            (
+                *i,
+                matches!(arg, KeyExpression::KeyName(KeyName::Unknown(_))),
+            )
+        });
+        check_strict_arguments(function_name, unknown_key_names)?;
+    }
+    let args = args.into_iter().map(|(_, arg)| arg).collect();
+    Ok((input, (function_name, args)))
+}
+
+/// Fail on the first unknown key name among the arguments in a position where the
+/// function `function_name` expects a key; other identifiers (e.g. group names) may be
+/// anything. Each argument is given as the input it was parsed from, and whether it is
+/// an unknown key name.
+fn check_strict_arguments<'a>(
+    function_name: &str,
+    args: impl IntoIterator<Item = (&'a str, bool)>,
+) -> Result<(), nom::Err<KeyParseError<'a>>> {
+    let Some(signature) = key_function_signature(function_name) else {
+        return Ok(());
+    };
+    for (position, (arg_input, is_unknown_key_name)) in args.into_iter().enumerate() {
+        let expects_key = matches!(
+            signature.arg_kind(position),
+            Some(ArgumentKind::KeyExpression | ArgumentKind::KeyName)
+        );
+        if expects_key && is_unknown_key_name {
+            return Err(strict_failure(arg_input, Expected::KnownKeyName));
         }
     }
+    Ok(())
 }
 
-pub(crate) trait KeyFunctionParser {
-    type Output;
-    fn parse_key_function(input: &str, separator: char) -> IResult<&str, Self::Output>;
+fn parse_key_function<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, KeyFunction> {
+    // Note that the `_parse_key_function_name_from_separator` approach (in
+    // `parse_key_function_call`) is how we determine if this input is the start of a
+    // key function call at all. This *requires* that key functions have at least one
+    // argument; otherwise, it's probably just an unrecognized flow key name.
+    //
+    // The call is parsed once, whatever the function, and only then classified by its
+    // arguments: re-parsing a call that doesn't fit its function would re-parse every
+    // call nested in it, at every level.
+    let (rest, (function_name, args)) = parse_key_function_call(opts, input)?;
+    let function = classify_key_function_call(opts, input, function_name, args)?;
+    Ok((rest, function))
 }
 
-impl KeyFunctionParser for UnknownKeyFunction {
-    type Output = Self;
-    fn parse_key_function(input: &str, separator: char) -> IResult<&str, Self::Output> {
-        let (input, function_name) = _parse_key_function_name_from_separator(separator)(input)?;
-        let (input, args) = parse_key_function_arguments(input, false)?;
-        Ok((
-            input,
-            Self {
-                function_name: function_name.to_string(),
-                args,
-            },
-        ))
-    }
-}
-
-impl KeyFunctionParser for GroupKeyFunction {
-    type Output = Self;
-    fn parse_key_function(input: &str, separator: char) -> IResult<&str, Self::Output> {
-        const KEY_FUNCTION_NAME_GROUP: &str = "group";
-        let (input, _) = terminated(tag(KEY_FUNCTION_NAME_GROUP), char(separator))(input)?;
-        let (input, key) = parse_key_function_argument(input)?;
-        let (input, group_names) =
-            many0(preceded(char(separator), alphanumeric1_or_underscore))(input)?;
-        let group_names: Vec<_> = group_names.into_iter().map(String::from).collect();
-        Ok((
-            input,
-            GroupKeyFunction {
-                key: Box::new(key),
-                group_names,
-            },
-        ))
+/// Type a parsed call to `function_name` as [`KeyFunction::from_call`] does. Built-in
+/// functions take precedence over registered ones. A call to a known function whose
+/// arguments don't match its signature is an unknown key function call;
+/// `key_signature::check_key_definition` explains what is wrong with it. In strict
+/// mode, that (like a call to an unknown function) is an error instead.
+fn classify_key_function_call<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+    function_name: &str,
+    args: Vec<KeyExpression>,
+) -> Result<KeyFunction, nom::Err<KeyParseError<'a>>> {
+    let function = KeyFunction::from_call(function_name, args);
+    if opts.strict && matches!(function, KeyFunction::Unknown(_)) {
+        return Err(unknown_call_failure(input, function_name));
     }
+    Ok(function)
 }
 
-impl KeyFunctionParser for CountryKeyFunction {
-    type Output = Self;
-    fn parse_key_function(input: &str, separator: char) -> IResult<&str, Self::Output> {
-        const KEY_FUNCTION_NAME_COUNTRY: &str = "country";
-        let (input, _) = terminated(tag(KEY_FUNCTION_NAME_COUNTRY), char(separator))(input)?;
-        let (input, arg) = alphanumeric1(input)?;
-        Ok((
-            input,
-            CountryKeyFunction {
-                arg: arg.to_string(),
-            },
-        ))
-    }
+/// The strict mode failure for a call at `input` to `function_name` that could only be
+/// typed as an unknown key function call.
+fn unknown_call_failure<'a>(input: &'a str, function_name: &str) -> nom::Err<KeyParseError<'a>> {
+    let expected = if key_function_signature(function_name).is_some() {
+        Expected::ValidArguments
+    } else {
+        Expected::KnownKeyFunction
+    };
+    strict_failure(input, expected)
 }
 
 // parser: key expression
 
-fn parse_key_expression(input: &str) -> IResult<&str, KeyExpression> {
+fn parse_key_expression<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, KeyExpression> {
     // Try parsing a key function first, then fall back to a key name
-    let (input, key_expression) = map(parse_key_function, KeyExpression::KeyFunction)(input)
-        .or_else(|_| map(parse_key_name_or_unknown, KeyExpression::KeyName)(input))?;
-    Ok((input, key_expression))
+    let function_error = match parse_key_function(opts, input) {
+        Ok((input, key_function)) => return Ok((input, key_function.into())),
+        Err(nom::Err::Error(e)) => KeyParseError::add_context(input, "key function call", e),
+        Err(e) => return Err(e),
+    };
+    let (rest, key_name) =
+        parse_key_name_or_unknown(input).map_err(|e| e.map(|e| e.or(function_error.clone())))?;
+    // A key name can't be followed by a key function argument separator, so this is
+    // a key function call that failed to parse; report why.
+    if rest.starts_with(opts.fn_arg_sep) {
+        return Err(nom::Err::Error(function_error));
+    }
+    if opts.strict && matches!(key_name, KeyName::Unknown(_)) {
+        return Err(strict_failure(input, Expected::KnownKeyName));
+    }
+    Ok((rest, key_name.into()))
 }
 
 // parser: key definition
 
-pub fn parse_key_definition(input: &str) -> IResult<&str, KeyDefinition> {
+pub fn parse_key_definition(input: &str) -> KeyParseResult<'_, KeyDefinition> {
+    parse_key_definition_with(&KEY_PARSEOPTS, input)
+}
+
+/// Parse a key definition written with the separators in `opts`.
+pub fn parse_key_definition_with<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, KeyDefinition> {
     map(
-        separated_list1(char(KEY_PARSEOPTS.key_def_sep), parse_key_expression),
+        |i| parse_key_expressions(opts, i, |i| parse_key_expression(opts, i)),
         |keys: Vec<KeyExpression>| KeyDefinition { keys },
     )(input)
 }
 
-/// Take a `nom` parser's results and do the appropriate conversions and cloning that
-/// yields an owned `anyhow` result (that doesn't require the input's data to have any
-/// specific lifetime).
-pub fn finish_nom_parse<T>(result: IResult<&str, T>) -> anyhow::Result<(String, T)> {
-    match result.finish() {
-        core::result::Result::Ok((s, key_definition)) => {
-            anyhow::Result::Ok((s.to_owned(), key_definition))
+/// Parse a complete key definition. Unlike [`parse_key_definition`], all of `input`
+/// must be consumed, and a failure is reported as a [`KeySyntaxError`] positioned in
+/// `input`.
+pub fn parse_key_definition_complete(input: &str) -> Result<KeyDefinition, KeySyntaxError> {
+    parse_key_definition_complete_with(&KEY_PARSEOPTS, input)
+}
+
+/// Parse a complete key definition written with the separators in `opts`; see
+/// [`parse_key_definition_complete`].
+pub fn parse_key_definition_complete_with(
+    opts: &SflowRtKeyParserOptions,
+    input: &str,
+) -> Result<KeyDefinition, KeySyntaxError> {
+    let (rest, definition) = parse_key_definition_with(opts, input)
+        .finish()
+        .map_err(|e| e.into_syntax_error(input))?;
+    if rest.is_empty() {
+        return Ok(definition);
+    }
+    Err(leftover_error(opts, rest).into_syntax_error(input))
+}
+
+/// Parse a key definition, recovering from errors: a key expression that fails to
+/// parse is skipped up to the next key definition separator, and parsing continues
+/// from there. Returns the key expressions that did parse, and a diagnostic for each
+/// that didn't, positioned in `input`.
+pub fn parse_key_definition_recovering(input: &str) -> (KeyDefinition, Vec<KeySyntaxError>) {
+    parse_key_definition_recovering_with(&KEY_PARSEOPTS, input)
+}
+
+/// Parse a key definition written with the separators in `opts`, recovering from
+/// errors; see [`parse_key_definition_recovering`].
+pub fn parse_key_definition_recovering_with(
+    opts: &SflowRtKeyParserOptions,
+    input: &str,
+) -> (KeyDefinition, Vec<KeySyntaxError>) {
+    let mut keys = Vec::new();
+    let mut errors = Vec::new();
+    if let Err(nom::Err::Failure(e) | nom::Err::Error(e)) = check_length(opts, input) {
+        errors.push(e.into_syntax_error(input));
+        return (KeyDefinition { keys }, errors);
+    }
+    for (index, (offset, element)) in split_key_definition(opts, input).into_iter().enumerate() {
+        if index >= opts.max_expressions {
+            let limit = KeyParseLimit::Expressions(opts.max_expressions);
+            let error = KeyParseError::new(element, Expected::Within(limit));
+            errors.push(error.into_syntax_error_at(input, offset));
+            break;
         }
-        core::result::Result::Err(e) => anyhow::Result::Err(anyhow::anyhow!(e.to_string()))
-            .context("parsing a flow key definition"),
+        let parsed = parse_key_expression(opts, element)
+            .finish()
+            .and_then(|(rest, key)| {
+                if rest.is_empty() {
+                    Ok(key)
+                } else {
+                    Err(leftover_error(opts, rest))
+                }
+            });
+        match parsed {
+            Ok(key) => keys.push(key),
+            Err(e) => {
+                let error_offset = offset + element.len() - e.remaining.len();
+                errors.push(e.into_syntax_error_at(input, error_offset));
+            }
+        }
+    }
+    (KeyDefinition { keys }, errors)
+}
+
+/// Split a key definition into its key expressions' text at each key definition
+/// separator that isn't nested in a key function argument or quoted in a string or
+/// regular expression literal, along with the byte offset of each in `input`.
+fn split_key_definition<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> Vec<(usize, &'a str)> {
+    let mut elements = Vec::new();
+    let mut depth: usize = 0;
+    let mut start = 0;
+    // the closing delimiter of the literal being skipped over, if any
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut previous = None;
+    for (i, c) in input.char_indices() {
+        let at_argument_start = previous == Some(opts.fn_arg_sep);
+        previous = Some(c);
+        if let Some(delimiter) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter {
+                quote = None;
+            }
+        } else if c == '"' || (c == '/' && at_argument_start) {
+            // a `/` only starts a regular expression at the start of an argument;
+            // elsewhere it's part of an IP prefix
+            quote = Some(c);
+        } else if c == opts.fn_nest_open {
+            depth += 1;
+        } else if c == opts.fn_nest_close {
+            depth = depth.saturating_sub(1);
+        } else if c == opts.key_def_sep && depth == 0 {
+            elements.push((start, &input[start..i]));
+            start = i + c.len_utf8();
+        }
+    }
+    elements.push((start, &input[start..]));
+    elements
+}
+
+/// Explain why a parse that should have consumed all of its input stopped at `rest`.
+fn leftover_error<'a>(opts: &SflowRtKeyParserOptions, rest: &'a str) -> KeyParseError<'a> {
+    // `separated_list1` stops, without an error, at the first list element after a
+    // separator that fails to parse, for both key expressions and key function
+    // arguments; parse it again to find out why.
+    if let Some(after_separator) = rest.strip_prefix(opts.key_def_sep) {
+        if let Err(e) = parse_key_expression(opts, after_separator).finish() {
+            return e;
+        }
+    }
+    if let Some(after_separator) = rest.strip_prefix(opts.fn_arg_sep) {
+        if let Err(e) = parse_key_function_argument(opts, after_separator).finish() {
+            return e;
+        }
+    }
+    KeyParseError::new(rest, Expected::Char(opts.key_def_sep))
+        .or(KeyParseError::new(rest, Expected::EndOfInput))
+}
+
+// parser: spans
+
+/// Parse a complete key definition into a tree with source spans; see
+/// [`super::key_span`]. Errors are reported as by [`parse_key_definition_complete`].
+pub fn parse_key_definition_spanned(input: &str) -> Result<SpannedKeyDefinition, KeySyntaxError> {
+    parse_key_definition_spanned_with(&KEY_PARSEOPTS, input)
+}
+
+/// Parse a complete key definition written with the separators in `opts` into a tree
+/// with source spans; see [`parse_key_definition_spanned`].
+pub fn parse_key_definition_spanned_with(
+    opts: &SflowRtKeyParserOptions,
+    input: &str,
+) -> Result<SpannedKeyDefinition, KeySyntaxError> {
+    let (rest, keys) = parse_key_expressions(opts, input, |i| {
+        parse_spanned_key_expression(opts, input, i)
+    })
+    .finish()
+    .map_err(|e| e.into_syntax_error(input))?;
+    if rest.is_empty() {
+        return Ok(SpannedKeyDefinition { keys });
+    }
+    Err(leftover_error(opts, rest).into_syntax_error(input))
+}
+
+/// The span of the text consumed from `input` up to `rest`, where both are suffixes
+/// of `source`.
+fn span_of(source: &str, input: &str, rest: &str) -> Span {
+    Span::new(source.len() - input.len(), source.len() - rest.len())
+}
+
+/// Parse a key expression with spans, failing as [`parse_key_expression`] does.
+fn parse_spanned_key_expression<'a>(
+    opts: &SflowRtKeyParserOptions,
+    source: &'a str,
+    input: &'a str,
+) -> KeyParseResult<'a, SpannedKeyExpression> {
+    let function_error = match parse_spanned_key_function(opts, source, input) {
+        Ok((rest, function)) => return Ok((rest, SpannedKeyExpression::KeyFunction(function))),
+        Err(nom::Err::Error(e)) => KeyParseError::add_context(input, "key function call", e),
+        Err(e) => return Err(e),
+    };
+    let (rest, key_name) =
+        parse_key_name_or_unknown(input).map_err(|e| e.map(|e| e.or(function_error.clone())))?;
+    if rest.starts_with(opts.fn_arg_sep) {
+        return Err(nom::Err::Error(function_error));
+    }
+    if opts.strict && matches!(key_name, KeyName::Unknown(_)) {
+        return Err(strict_failure(input, Expected::KnownKeyName));
+    }
+    let span = span_of(source, input, rest);
+    Ok((rest, SpannedKeyExpression::KeyName { key_name, span }))
+}
+
+/// Parse a key function call with spans, typing it from its spanned arguments as
+/// [`parse_key_function`] types the call without them.
+fn parse_spanned_key_function<'a>(
+    opts: &SflowRtKeyParserOptions,
+    source: &'a str,
+    input: &'a str,
+) -> KeyParseResult<'a, SpannedKeyFunction> {
+    let (after_name, function_name) = parse_key_function_name(input)?;
+    let name_span = span_of(source, input, after_name);
+    let argument = |i| {
+        let (rest, arg) = parse_spanned_key_function_argument(opts, source, i)?;
+        Ok((rest, (i, arg)))
+    };
+    let (rest, args): (_, Vec<(&str, SpannedKeyExpression)>) = preceded(
+        char(opts.fn_arg_sep),
+        separated_list1(char(opts.fn_arg_sep), argument),
+    )(after_name)?;
+    if opts.strict {
+        let unknown_key_names = args.iter().map(|(i, arg)| {
+            let is_unknown_key_name = matches!(
+                arg,
+                SpannedKeyExpression::KeyName {
+                    key_name: KeyName::Unknown(_),
+                    ..
+                }
+            );
+            (*i, is_unknown_key_name)
+        });
+        check_strict_arguments(function_name, unknown_key_names)?;
+    }
+    let args: Vec<SpannedKeyExpression> = args.into_iter().map(|(_, arg)| arg).collect();
+    let kind = type_call(function_name, &args);
+    if opts.strict && kind == KeyFunctionKind::Unknown {
+        return Err(unknown_call_failure(input, function_name));
+    }
+    Ok((
+        rest,
+        SpannedKeyFunction {
+            kind,
+            function_name: function_name.to_string(),
+            name_span,
+            args,
+            span: span_of(source, input, rest),
+        },
+    ))
+}
+
+/// Parse a key function argument with spans, failing as
+/// [`parse_key_function_argument`] does.
+fn parse_spanned_key_function_argument<'a>(
+    opts: &SflowRtKeyParserOptions,
+    source: &'a str,
+    input: &'a str,
+) -> KeyParseResult<'a, SpannedKeyExpression> {
+    context(
+        "key function argument",
+        alt((
+            |i| {
+                parse_nested(opts, i, |opts, i| {
+                    map(
+                        |i| parse_spanned_key_function(opts, source, i),
+                        SpannedKeyExpression::KeyFunction,
+                    )(i)
+                })
+            },
+            |i| {
+                let (rest, literal) = parse_literal(i)?;
+                let span = span_of(source, i, rest);
+                Ok((rest, SpannedKeyExpression::Literal { literal, span }))
+            },
+            |i| {
+                let (rest, key_name) = parse_key_function_identifier_argument(i)?;
+                let span = span_of(source, i, rest);
+                Ok((rest, SpannedKeyExpression::KeyName { key_name, span }))
+            },
+        )),
+    )(input)
+}
+
+/// Take a `nom` parser's results and do the appropriate conversions and cloning that
+/// yields an owned result (that doesn't require the input's data to have any specific
+/// lifetime). `input` is the full input that was parsed, which errors are positioned in.
+pub fn finish_nom_parse<T>(
+    input: &str,
+    result: KeyParseResult<'_, T>,
+) -> Result<(String, T), KeyError> {
+    let (rest, output) = result
+        .finish()
+        .map_err(|e| KeyError::from(e.into_syntax_error(input)))?;
+    Ok((rest.to_owned(), output))
+}
+
+// parser: borrowed
+
+/// Parse a complete key definition into a tree that borrows from `input`; see
+/// [`super::key_borrowed`]. Errors are reported as by [`parse_key_definition_complete`].
+pub fn parse_key_definition_borrowed(
+    input: &str,
+) -> Result<BorrowedKeyDefinition<'_>, KeySyntaxError> {
+    parse_key_definition_borrowed_with(&KEY_PARSEOPTS, input)
+}
+
+/// Parse a complete key definition written with the separators in `opts` into a tree
+/// that borrows from `input`; see [`parse_key_definition_borrowed`].
+///
+/// Input that isn't well-formed is parsed a second time, by the owned parsers, which
+/// explain in more detail what is wrong with it; so a syntax error costs as much as
+/// an owned parse. Exceeding a limit, a malformed literal, and (in strict mode) an
+/// unknown name are reported without one.
+pub fn parse_key_definition_borrowed_with<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> Result<BorrowedKeyDefinition<'a>, KeySyntaxError> {
+    let parsed = parse_key_expressions(opts, input, |i| parse_borrowed_key_expression(opts, i));
+    if let Err(nom::Err::Failure(e)) = parsed {
+        return Err(e.into_syntax_error(input));
     }
+    let error = match parsed.finish() {
+        Ok(("", keys)) => return Ok(BorrowedKeyDefinition { keys }),
+        Ok((rest, _)) => leftover_error(opts, rest),
+        Err(e) => e,
+    };
+    Err(parse_key_definition_complete_with(opts, input)
+        .err()
+        .unwrap_or_else(|| error.into_syntax_error(input)))
+}
+
+fn parse_borrowed_key_expression<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, BorrowedKeyExpression<'a>> {
+    let (rest, expression) = alt((
+        map(
+            |i| parse_borrowed_key_function(opts, i),
+            BorrowedKeyExpression::KeyFunction,
+        ),
+        // a key name can't be followed by a key function argument separator
+        map(
+            terminated(parse_borrowed_key_name, not(char(opts.fn_arg_sep))),
+            BorrowedKeyExpression::KeyName,
+        ),
+    ))(input)?;
+    if opts.strict
+        && matches!(
+            expression,
+            BorrowedKeyExpression::KeyName(BorrowedKeyName::Unknown(_))
+        )
+    {
+        return Err(strict_failure(input, Expected::KnownKeyName));
+    }
+    Ok((rest, expression))
+}
+
+fn parse_borrowed_key_name(input: &str) -> KeyParseResult<'_, BorrowedKeyName<'_>> {
+    map(identifier, BorrowedKeyName::from)(input)
+}
+
+fn parse_borrowed_key_function<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, BorrowedKeyFunction<'a>> {
+    let (rest, (function_name, args)) = pair(
+        _parse_key_function_name_from_separator(opts.fn_arg_sep),
+        separated_list1(char(opts.fn_arg_sep), |i| {
+            let (rest, arg) = parse_borrowed_key_function_argument(opts, i)?;
+            Ok((rest, (i, arg)))
+        }),
+    )(input)?;
+    if opts.strict {
+        let unknown_key_names = args.iter().map(|(i, arg)| {
+            let is_unknown = matches!(
+                arg,
+                BorrowedKeyExpression::KeyName(BorrowedKeyName::Unknown(_))
+            );
+            (*i, is_unknown)
+        });
+        check_strict_arguments(function_name, unknown_key_names)?;
+    }
+    let args = args.into_iter().map(|(_, arg)| arg).collect();
+    let function = BorrowedKeyFunction::from_call(function_name, args);
+    if opts.strict && matches!(function, BorrowedKeyFunction::Unknown { .. }) {
+        return Err(unknown_call_failure(input, function_name));
+    }
+    Ok((rest, function))
+}
+
+fn parse_borrowed_key_function_argument<'a>(
+    opts: &SflowRtKeyParserOptions,
+    input: &'a str,
+) -> KeyParseResult<'a, BorrowedKeyExpression<'a>> {
+    context(
+        "key function argument",
+        alt((
+            |i| {
+                parse_nested(opts, i, |opts, i| {
+                    map(
+                        |i| parse_borrowed_key_function(opts, i),
+                        BorrowedKeyExpression::KeyFunction,
+                    )(i)
+                })
+            },
+            map(literal, BorrowedKeyExpression::Literal),
+            map(parse_borrowed_key_name, BorrowedKeyExpression::KeyName),
+        )),
+    )(input)
 }
 
 // tests //////////////////////////////////////////////////////////////////////////////
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::key::key_function::{CountryKeyFunction, GroupKeyFunction, UnknownKeyFunction};
+    use crate::key::key_literal::{AddressLiteral, LiteralKind};
+    use crate::key::key_signature::KEY_FUNCTION_SIGNATURES;
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[rstest::rstest]
     #[case("ipsource", true, Some(KeyName::IpSource))]
@@ -229,6 +981,7 @@ mod tests {
     #[case("unknownkey", false, None)]
     #[case("ip6source", true, Some(KeyName::Ip6Source))]
     #[case("ip5source", false, None)]
+    #[case("ip6_offset", true, Some(KeyName::Ip6Offset))]
     fn test_parse_key_name(
         #[case] key_name: &str,
         #[case] is_known: bool,
@@ -258,7 +1011,7 @@ mod tests {
     fn test_parse_key_function() {
         // key function: country //////////////////////////////////////////////////////
         assert_eq!(
-            parse_key_function("country:ipsource"),
+            parse_key_function(&KEY_PARSEOPTS, "country:ipsource"),
             Ok((
                 "",
                 KeyFunction::from(CountryKeyFunction {
@@ -271,7 +1024,7 @@ mod tests {
         // key function: group, arity 1
 
         assert_eq!(
-            parse_key_function("group:ipdestination:gro_up1"),
+            parse_key_function(&KEY_PARSEOPTS, "group:ipdestination:gro_up1"),
             Ok((
                 "",
                 KeyFunction::Group(GroupKeyFunction {
@@ -282,7 +1035,7 @@ mod tests {
         );
         // key function: group, arity 2
         assert_eq!(
-            parse_key_function("group:ipsource:gro_up1:group2"),
+            parse_key_function(&KEY_PARSEOPTS, "group:ipsource:gro_up1:group2"),
             Ok((
                 "",
                 KeyFunction::Group(GroupKeyFunction {
@@ -293,7 +1046,10 @@ mod tests {
         );
         // key function: group, arity 3
         assert_eq!(
-            parse_key_function("group:ipsource:gro_up1:group2:_GROUP_THr33_"),
+            parse_key_function(
+                &KEY_PARSEOPTS,
+                "group:ipsource:gro_up1:group2:_GROUP_THr33_"
+            ),
             Ok((
                 "",
                 KeyFunction::Group(GroupKeyFunction {
@@ -307,11 +1063,24 @@ mod tests {
             ))
         );
 
+        // key function: group, signature mismatch (no group names) falls back to an
+        // unknown key function call
+        assert_eq!(
+            parse_key_function(&KEY_PARSEOPTS, "group:ipsource"),
+            Ok((
+                "",
+                KeyFunction::Unknown(UnknownKeyFunction {
+                    function_name: "group".to_string(),
+                    args: vec![KeyExpression::KeyName(KeyName::IpSource)],
+                })
+            ))
+        );
+
         // key function: unknown //////////////////////////////////////////////////////
 
         // key function: unknown, arity 1, basic
         assert_eq!(
-            parse_key_function("unknownfunc:ipdestination"),
+            parse_key_function(&KEY_PARSEOPTS, "unknownfunc:ipdestination"),
             Ok((
                 "",
                 KeyFunction::Unknown(UnknownKeyFunction {
@@ -323,7 +1092,10 @@ mod tests {
 
         // key function: unknown, arity 1, with nesting
         assert_eq!(
-            parse_key_function("unknownfunc:[group:ipdestination:gro_up1:group2]"),
+            parse_key_function(
+                &KEY_PARSEOPTS,
+                "unknownfunc:[group:ipdestination:gro_up1:group2]"
+            ),
             Ok((
                 "",
                 KeyFunction::Unknown(UnknownKeyFunction {
@@ -339,14 +1111,37 @@ mod tests {
         );
     }
 
+    /// Every built-in key function has a call that types as a known one; `group:` and
+    /// `country:` as their own variants, and the rest as calls typed by signature.
+    #[test]
+    fn test_builtin_key_functions() {
+        let cases = [
+            ("group:ipsource:a", KeyFunctionKind::Group),
+            ("country:ipsource", KeyFunctionKind::Country),
+            ("mask:ipsource:24", KeyFunctionKind::Custom),
+            ("or:ipsource:ip6source", KeyFunctionKind::Custom),
+        ];
+        let opts = KEY_PARSEOPTS.with_strict(true);
+        let mut names = Vec::new();
+        for (input, kind) in cases {
+            let (_, function) = parse_key_function(&opts, input).expect("should parse");
+            assert_eq!(KeyFunctionKind::from(&function), kind, "{input}");
+            names.extend(input.split(':').next());
+        }
+        let mut builtin: Vec<_> = KEY_FUNCTION_SIGNATURES.keys().copied().collect();
+        builtin.sort_unstable();
+        names.sort_unstable();
+        assert_eq!(names, builtin);
+    }
+
     #[test]
     fn test_parse_key_expression() {
         assert_eq!(
-            parse_key_expression("ipsource"),
+            parse_key_expression(&KEY_PARSEOPTS, "ipsource"),
             Ok(("", KeyExpression::KeyName(KeyName::IpSource)))
         );
         assert_eq!(
-            parse_key_expression("country:ipsource"),
+            parse_key_expression(&KEY_PARSEOPTS, "country:ipsource"),
             Ok((
                 "",
                 KeyExpression::KeyFunction(KeyFunction::Country(CountryKeyFunction {
@@ -355,7 +1150,7 @@ mod tests {
             ))
         );
         assert_eq!(
-            parse_key_expression("unknownfunc:[group:ipsource:group1:group2]"),
+            parse_key_expression(&KEY_PARSEOPTS, "unknownfunc:[group:ipsource:group1:group2]"),
             Ok((
                 "",
                 KeyExpression::KeyFunction(KeyFunction::Unknown(UnknownKeyFunction {
@@ -375,7 +1170,7 @@ mod tests {
     fn test_parse_unknown_key_function_with_various_arguments() {
         // Non-nested argument
         assert_eq!(
-            parse_key_function("unknownfunc:ipdestination"),
+            parse_key_function(&KEY_PARSEOPTS, "unknownfunc:ipdestination"),
             Ok((
                 "",
                 KeyFunction::Unknown(UnknownKeyFunction {
@@ -387,7 +1182,10 @@ mod tests {
 
         // Nested argument using brackets
         assert_eq!(
-            parse_key_function("unknownfunc:[group:ipdestination:group1:group2]"),
+            parse_key_function(
+                &KEY_PARSEOPTS,
+                "unknownfunc:[group:ipdestination:group1:group2]"
+            ),
             Ok((
                 "",
                 KeyFunction::Unknown(UnknownKeyFunction {
@@ -405,6 +1203,7 @@ mod tests {
         // Combination of both nested and non-nested arguments
         assert_eq!(
             parse_key_function(
+                &KEY_PARSEOPTS,
                 "unknownfunc:ipdestination:[group:ipdestination:group1:group2]:unknownkey"
             ),
             Ok((
@@ -423,4 +1222,473 @@ mod tests {
             ))
         );
     }
+
+    /// Key names, key function names and group names are all identifiers, and follow
+    /// the same rules.
+    #[rstest::rstest]
+    #[case("ip6_offset", KeyName::Ip6Offset.into())]
+    #[case("schlüssel_1", KeyName::Unknown("schlüssel_1".to_string()).into())]
+    #[case(
+        "my_func:ip6_offset",
+        KeyFunction::Unknown(UnknownKeyFunction {
+            function_name: "my_func".to_string(),
+            args: vec![KeyName::Ip6Offset.into()],
+        })
+        .into()
+    )]
+    #[case(
+        "grüppe:ipsource",
+        KeyFunction::Unknown(UnknownKeyFunction {
+            function_name: "grüppe".to_string(),
+            args: vec![KeyName::IpSource.into()],
+        })
+        .into()
+    )]
+    #[case(
+        "group:ip6_offset:grüppe_1:_",
+        KeyFunction::Group(GroupKeyFunction {
+            key: Box::new(KeyName::Ip6Offset.into()),
+            group_names: vec!["grüppe_1".to_string(), "_".to_string()],
+        })
+        .into()
+    )]
+    fn test_parse_identifiers(#[case] input: &str, #[case] expected: KeyExpression) {
+        assert_eq!(
+            parse_key_expression(&KEY_PARSEOPTS, input),
+            Ok(("", expected.clone()))
+        );
+        assert_eq!(expected.to_string(), input);
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,group:[country:ipsource", 32, &[Expected::Char(']')])]
+    #[case("ipsource;ip6source", 8, &[Expected::Char(','), Expected::EndOfInput])]
+    #[case("ipsource,,ip6source", 9, &[Expected::Identifier])]
+    #[case(
+        "group:ipsource:",
+        15,
+        &[Expected::Char('['), Expected::Identifier, Expected::AnyLiteral]
+    )]
+    #[case(
+        "country:",
+        8,
+        &[Expected::Char('['), Expected::Identifier, Expected::AnyLiteral]
+    )]
+    #[case("group:ipsource:\"trusted", 23, &[Expected::Char('"')])]
+    #[case("group:ipsource:\"a\\b\"", 17, &[Expected::EscapeSequence])]
+    #[case("f:ipsource:10.0.0.300", 11, &[Expected::Literal(LiteralKind::Address)])]
+    #[case("f:ipsource:{10.0.0.0/33}", 12, &[Expected::Literal(LiteralKind::Address)])]
+    #[case("f:ipsource:{fe80::", 18, &[Expected::Char('}')])]
+    #[case("f:99999999999999999999", 2, &[Expected::Literal(LiteralKind::Integer)])]
+    #[case("f:/^10\\.", 8, &[Expected::Char('/')])]
+    #[case("", 0, &[Expected::Identifier])]
+    fn test_parse_key_definition_complete_errors(
+        #[case] input: &str,
+        #[case] offset: usize,
+        #[case] expected: &[Expected],
+    ) {
+        let error = parse_key_definition_complete(input).expect_err("should fail to parse");
+        assert_eq!(error.input, input);
+        assert_eq!(error.offset, offset, "{}", error.render());
+        assert_eq!(error.expected, expected, "{}", error.render());
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,ipsorce", 9, Expected::KnownKeyName)]
+    #[case("ipsource,ipsorce:ip6source", 9, Expected::KnownKeyFunction)]
+    #[case("group:[country:mykey]:trusted", 15, Expected::KnownKeyName)]
+    #[case("group:mykey:trusted", 6, Expected::KnownKeyName)]
+    #[case("group:ipsource:[f:ipsource]", 16, Expected::KnownKeyFunction)]
+    #[case("ipsource,country:ipsource:ip6source", 9, Expected::ValidArguments)]
+    #[case("group:ipsource", 0, Expected::ValidArguments)]
+    #[case("country:ipsource:\"a", 19, Expected::Char('"'))]
+    fn test_parse_key_definition_strict_errors(
+        #[case] input: &str,
+        #[case] offset: usize,
+        #[case] expected: Expected,
+    ) {
+        let opts = KEY_PARSEOPTS.with_strict(true);
+        let error =
+            parse_key_definition_complete_with(&opts, input).expect_err("should fail to parse");
+        assert_eq!(error.offset, offset, "{}", error.render());
+        assert_eq!(error.expected, vec![expected.clone()], "{}", error.render());
+        assert_eq!(parse_key_definition_borrowed_with(&opts, input), Err(error));
+        // lenient parsing accepts anything that is only unknown
+        if !matches!(expected, Expected::Char(_)) {
+            assert!(parse_key_definition_complete(input).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_key_definition_strict() {
+        let opts = KEY_PARSEOPTS.with_strict(true);
+        // group names and string arguments needn't be known
+        let input = "ipsource,group:[country:ip6source]:trusted:ipsource:\"a b\"";
+        assert_eq!(
+            parse_key_definition_complete_with(&opts, input),
+            parse_key_definition_complete(input)
+        );
+        let (definition, errors) =
+            parse_key_definition_recovering_with(&opts, "ipsource,mykey,ip6source");
+        assert_eq!(definition.len(), 2);
+        assert_eq!(errors[0].offset, 9);
+        assert_eq!(errors[0].expected, vec![Expected::KnownKeyName]);
+    }
+
+    #[test]
+    fn test_parse_key_definition_complete() {
+        assert_eq!(
+            parse_key_definition_complete("ipsource,country:ip6source"),
+            Ok(KeyDefinition {
+                keys: vec![
+                    KeyExpression::KeyName(KeyName::IpSource),
+                    KeyExpression::KeyFunction(KeyFunction::Country(CountryKeyFunction {
+                        arg: "ip6source".to_string()
+                    })),
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_split_key_definition() {
+        assert_eq!(
+            split_key_definition(&KEY_PARSEOPTS, "ipsource,group:[x:a,b]:c,,"),
+            vec![(0, "ipsource"), (9, "group:[x:a,b]:c"), (25, ""), (26, "")]
+        );
+        assert_eq!(
+            split_key_definition(&KEY_PARSEOPTS, "a]],b"),
+            vec![(0, "a]]"), (4, "b")]
+        );
+        assert_eq!(
+            split_key_definition(&KEY_PARSEOPTS, r#"f:"a,\",b":/,/,g:10.0.0.0/8,c"#),
+            vec![(0, r#"f:"a,\",b":/,/"#), (15, "g:10.0.0.0/8"), (28, "c")]
+        );
+    }
+
+    #[test]
+    fn test_parse_key_definition_recovering() {
+        let input = "ipsource,group:[country:ipsource,ip6source;,,country:ip6destination";
+        let (definition, errors) = parse_key_definition_recovering(input);
+        // the unclosed bracket swallows the rest of the definition
+        assert_eq!(
+            definition,
+            KeyDefinition {
+                keys: vec![KeyExpression::KeyName(KeyName::IpSource)]
+            }
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, 32);
+        assert_eq!(errors[0].expected, vec![Expected::Char(']')]);
+
+        let input = "ipsource,country:,ip6source;,,country:ip6destination,:";
+        let (definition, errors) = parse_key_definition_recovering(input);
+        assert_eq!(
+            definition,
+            KeyDefinition {
+                keys: vec![
+                    KeyExpression::KeyName(KeyName::IpSource),
+                    KeyExpression::KeyFunction(KeyFunction::Country(CountryKeyFunction {
+                        arg: "ip6destination".to_string()
+                    })),
+                ]
+            }
+        );
+        let errors: Vec<(usize, Vec<Expected>)> =
+            errors.into_iter().map(|e| (e.offset, e.expected)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    17,
+                    vec![
+                        Expected::Char('['),
+                        Expected::Identifier,
+                        Expected::AnyLiteral
+                    ]
+                ),
+                (27, vec![Expected::Char(','), Expected::EndOfInput]),
+                (29, vec![Expected::Identifier]),
+                (53, vec![Expected::Identifier]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_key_definition_recovering_without_errors() {
+        let input = "ipsource,group:[country:ip6source]:a:b";
+        let (definition, errors) = parse_key_definition_recovering(input);
+        assert!(errors.is_empty());
+        assert_eq!(Ok(definition), parse_key_definition_complete(input));
+    }
+
+    #[test]
+    fn test_parse_key_definition_with_options() {
+        let opts = KEY_PARSEOPTS
+            .with_key_def_sep(';')
+            .with_fn_arg_sep('|')
+            .with_fn_nest('(', ')');
+        assert_eq!(opts.validate(), Ok(()));
+        let expected = KeyDefinition {
+            keys: vec![
+                KeyExpression::KeyName(KeyName::IpSource),
+                KeyExpression::KeyFunction(KeyFunction::Group(GroupKeyFunction {
+                    key: Box::new(KeyExpression::KeyFunction(KeyFunction::Country(
+                        CountryKeyFunction {
+                            arg: "ip6source".to_string(),
+                        },
+                    ))),
+                    group_names: vec!["trusted".to_string(), "bad".to_string()],
+                })),
+            ],
+        };
+        assert_eq!(
+            parse_key_definition_complete_with(
+                &opts,
+                "ipsource;group|(country|ip6source)|trusted|bad"
+            ),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            parse_key_definition_complete("ipsource,group:[country:ip6source]:trusted:bad"),
+            Ok(expected)
+        );
+        // the default separators are now just unexpected characters
+        let (definition, errors) =
+            parse_key_definition_recovering_with(&opts, "ipsource,ip6source;x");
+        assert_eq!(definition.keys.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, 8);
+        assert_eq!(
+            errors[0].expected,
+            vec![Expected::Char(';'), Expected::EndOfInput]
+        );
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert_eq!(KEY_PARSEOPTS.validate(), Ok(()));
+        assert_eq!(
+            KEY_PARSEOPTS.with_key_def_sep(':').validate(),
+            Err(InvalidKeyParserOptions::DuplicateSeparator(':'))
+        );
+        assert_eq!(
+            KEY_PARSEOPTS.with_fn_arg_sep('_').validate(),
+            Err(InvalidKeyParserOptions::IdentifierCharacter('_'))
+        );
+        assert_eq!(
+            KEY_PARSEOPTS.with_fn_nest('{', '}').validate(),
+            Err(InvalidKeyParserOptions::LiteralCharacter('{'))
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("24", Literal::Integer(24))]
+    #[case("-1", Literal::Integer(-1))]
+    #[case(r#""a,b""#, Literal::String("a,b".to_string()))]
+    #[case(r#""say \"hi\" \\o/""#, Literal::String(r#"say "hi" \o/"#.to_string()))]
+    #[case(r"/^10\.0\/8/", Literal::Regex(r"^10\.0/8".to_string()))]
+    #[case("10.0.0.0/8", Literal::Address(AddressLiteral {
+        address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+        prefix_len: Some(8),
+    }))]
+    #[case("{192.168.1.1}", Literal::Address(AddressLiteral {
+        address: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+        prefix_len: None,
+    }))]
+    #[case("{fe80::/10}", Literal::Address(AddressLiteral {
+        address: "fe80::".parse().expect("valid IPv6 address"),
+        prefix_len: Some(10),
+    }))]
+    fn test_parse_literal(#[case] input: &str, #[case] expected: Literal) {
+        assert_eq!(parse_literal(input), Ok(("", expected.clone())));
+        // and back again
+        assert_eq!(parse_literal(&expected.to_string()), Ok(("", expected)));
+    }
+
+    #[test]
+    fn test_parse_literal_arguments() {
+        assert_eq!(
+            parse_key_definition_complete(r#"group:ipsource:"my group":"a,b",f:ipsource:24:abc"#),
+            Ok(KeyDefinition {
+                keys: vec![
+                    KeyFunction::Group(GroupKeyFunction {
+                        key: Box::new(KeyName::IpSource.into()),
+                        group_names: vec!["my group".to_string(), "a,b".to_string()],
+                    })
+                    .into(),
+                    KeyFunction::Unknown(UnknownKeyFunction {
+                        function_name: "f".to_string(),
+                        args: vec![
+                            KeyName::IpSource.into(),
+                            Literal::Integer(24).into(),
+                            KeyName::Unknown("abc".to_string()).into(),
+                        ],
+                    })
+                    .into(),
+                ]
+            })
+        );
+        // digits followed by identifier characters are still an identifier
+        assert_eq!(
+            parse_key_function_argument(&KEY_PARSEOPTS, "24abc"),
+            Ok(("", KeyName::Unknown("24abc".to_string()).into()))
+        );
+        // a literal isn't a key, so `group:` can't group it
+        assert!(matches!(
+            parse_key_function(&KEY_PARSEOPTS, "group:24:trusted"),
+            Ok(("", KeyFunction::Unknown(_)))
+        ));
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("f:{}ipsource{}", "[f:".repeat(depth), "]".repeat(depth))
+    }
+
+    #[rstest::rstest]
+    #[case(KEY_PARSEOPTS, &nested(33), 98, KeyParseLimit::Depth(32))]
+    #[case(KEY_PARSEOPTS, &nested(4000), 98, KeyParseLimit::Depth(32))]
+    #[case(
+        KEY_PARSEOPTS.with_max_depth(0),
+        "group:[country:ipsource]:a",
+        6,
+        KeyParseLimit::Depth(0)
+    )]
+    #[case(
+        KEY_PARSEOPTS.with_max_expressions(2),
+        "ipsource,ip6source,ipdestination",
+        19,
+        KeyParseLimit::Expressions(2)
+    )]
+    #[case(
+        KEY_PARSEOPTS.with_max_length(10),
+        "ipsource,ip6source",
+        10,
+        KeyParseLimit::Length(10)
+    )]
+    // the limit falls in the middle of `ü`
+    #[case(
+        KEY_PARSEOPTS.with_max_length(18),
+        "group:ipsource:grüppe",
+        17,
+        KeyParseLimit::Length(18)
+    )]
+    #[case(
+        KEY_PARSEOPTS.with_strict(true).with_max_depth(1),
+        "group:[group:[country:ipsource]:b]:a",
+        13,
+        KeyParseLimit::Depth(1)
+    )]
+    fn test_parse_limits(
+        #[case] opts: SflowRtKeyParserOptions,
+        #[case] input: &str,
+        #[case] offset: usize,
+        #[case] limit: KeyParseLimit,
+    ) {
+        let errors = [
+            parse_key_definition_complete_with(&opts, input).map(|_| ()),
+            parse_key_definition_spanned_with(&opts, input).map(|_| ()),
+            parse_key_definition_borrowed_with(&opts, input).map(|_| ()),
+        ];
+        for error in errors {
+            let error = error.expect_err("should exceed a limit");
+            assert_eq!(error.exceeded_limit(), Some(limit), "{}", error.render());
+            assert_eq!(error.offset, offset, "{}", error.render());
+        }
+        let (_, errors) = parse_key_definition_recovering_with(&opts, input);
+        assert_eq!(errors[0].exceeded_limit(), Some(limit));
+        assert_eq!(errors[0].offset, offset);
+    }
+
+    #[test]
+    fn test_parse_at_limits() {
+        let opts = KEY_PARSEOPTS
+            .with_max_expressions(2)
+            .with_max_length(nested(32).len() + 9);
+        let input = format!("ipsource,{}", nested(32));
+        assert!(parse_key_definition_complete_with(&opts, &input).is_ok());
+        assert!(parse_key_definition_spanned_with(&opts, &input).is_ok());
+        assert!(parse_key_definition_borrowed_with(&opts, &input).is_ok());
+        let (_, errors) = parse_key_definition_recovering_with(&opts, &input);
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            KeyParseLimit::Depth(32).to_string(),
+            "at most 32 levels of nested key functions"
+        );
+    }
+
+    /// Deeply nested calls to the built-in functions, whether they fit the functions'
+    /// signatures or not, and whether they are well-formed or not, must parse in time
+    /// linear in their length; not in time exponential in their depth.
+    #[rstest::rstest]
+    #[case(format!("{}group:ipsource:a{}", "group:[".repeat(31), "]:a".repeat(31)))]
+    #[case(format!("{}ipsource{}", "country:[".repeat(32), "]".repeat(32)))]
+    #[case(format!("{}ipsource{}", "group:[".repeat(32), "]".repeat(32)))]
+    #[case(format!("{}ipsource:a{}", "country:[".repeat(32), "]:a".repeat(32)))]
+    #[case(format!("{}..x:", "group:[".repeat(32)))]
+    #[case(format!("{}x:", "country:[".repeat(32)))]
+    #[case(format!("ipsource,{}..x:,ip6source", "group:[country:[".repeat(16)))]
+    fn test_parse_deep_nesting_in_linear_time(#[case] input: String) {
+        for strict in [false, true] {
+            let opts = KEY_PARSEOPTS.with_strict(strict);
+            let start = std::time::Instant::now();
+            let _ = parse_key_definition_complete_with(&opts, &input);
+            let _ = parse_key_definition_recovering_with(&opts, &input);
+            let _ = parse_key_definition_spanned_with(&opts, &input);
+            let _ = parse_key_definition_borrowed_with(&opts, &input);
+            let elapsed = start.elapsed();
+            assert!(
+                elapsed < std::time::Duration::from_secs(1),
+                "parsing took {elapsed:?}: {input}"
+            );
+        }
+    }
+
+    proptest::proptest! {
+        /// No parser may panic, whatever the input; and whatever parses must print as
+        /// DSL that parses back to the same key definition. Inputs are either arbitrary
+        /// text, or made of the characters the DSL gives meaning to, which are far more
+        /// likely to get deep into the parsers.
+        #[test]
+        fn test_parsers_never_panic(
+            input in proptest::prop_oneof![
+                "\\PC{0,64}",
+                "[a-z0-9_:,\\[\\]\"/\\\\{}.\\-]{0,64}",
+                "(group:|country:|f:|\\[|\\]|ip6source|[,:\"/{}.\\-]|[0-9]{1,3}){0,32}",
+            ],
+            strict in proptest::bool::ANY,
+        ) {
+            let opts = KEY_PARSEOPTS.with_strict(strict);
+            let _ = parse_key_definition_with(&opts, &input);
+            let _ = parse_key_definition_recovering_with(&opts, &input);
+            let _ = parse_key_definition_spanned_with(&opts, &input);
+            let _ = parse_key_definition_borrowed_with(&opts, &input);
+            if let Ok(definition) = parse_key_definition_complete_with(&opts, &input) {
+                let printed = definition.to_string();
+                proptest::prop_assert_eq!(
+                    parse_key_definition_complete_with(&opts, &printed),
+                    Ok(definition),
+                    "{}",
+                    printed
+                );
+            }
+        }
+
+        /// The spanned parser accepts the same key definitions as the owned one, and
+        /// rejects the rest with the same errors.
+        #[test]
+        fn test_spanned_parser_agrees(
+            input in "(group:|country:|mask:|or:|f:|\\[|\\]|ip6source|x|[,:\"/{}.\\-]|[0-9]{1,3}){0,32}",
+            strict in proptest::bool::ANY,
+        ) {
+            let opts = KEY_PARSEOPTS.with_strict(strict);
+            proptest::prop_assert_eq!(
+                parse_key_definition_spanned_with(&opts, &input)
+                    .map(|definition| definition.to_key_definition()),
+                parse_key_definition_complete_with(&opts, &input),
+                "{}",
+                input
+            );
+        }
+    }
 }
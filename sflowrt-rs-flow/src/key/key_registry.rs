@@ -0,0 +1,220 @@
+//! Runtime registration of user-defined key functions.
+//!
+//! The built-in key functions (`group:`, `country:`, ...) are parsed into their own
+//! typed [`KeyFunction`] variants. Downstream crates can add their own functions by
+//! implementing [`KeyFunctionPlugin`] and calling [`register_key_function`]; calls to
+//! a registered function then parse as [`KeyFunction::Custom`] rather than
+//! [`KeyFunction::Unknown`].
+//!
+//! [`KeyFunction`]: super::KeyFunction
+//! [`KeyFunction::Custom`]: super::KeyFunction::Custom
+//! [`KeyFunction::Unknown`]: super::KeyFunction::Unknown
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, LazyLock, PoisonError, RwLock},
+};
+
+use super::key_lexer::is_identifier;
+use super::key_signature::{KeyFunctionSignature, SignatureError, KEY_FUNCTION_SIGNATURES};
+use super::KeyExpression;
+
+/// A user-defined key function.
+///
+/// All key functions share the DSL's call syntax (`name:arg1:arg2:...`), so a plugin
+/// doesn't parse text itself. Instead, the parser hands it the call's arguments,
+/// which it may validate (by default, against its [signature](Self::signature)).
+pub trait KeyFunctionPlugin: Send + Sync {
+    /// The function's signature. The plugin is registered under the signature's name.
+    fn signature(&self) -> KeyFunctionSignature;
+
+    /// Validate the arguments of a call to this function. If this returns an error,
+    /// the call is parsed as an unknown key function call instead.
+    fn parse_arguments(&self, args: &[KeyExpression]) -> Result<(), SignatureError> {
+        self.signature().check_args(args)
+    }
+
+    /// Evaluate the function, given the values of its arguments. Returns `None` if the
+    /// function produces no value for these arguments, or if the plugin provides no
+    /// evaluator (the default).
+    fn evaluate(&self, _args: &[&str]) -> Option<String> {
+        None
+    }
+}
+
+/// A key function couldn't be registered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegistrationError {
+    /// The name is that of a built-in key function.
+    BuiltinFunction(&'static str),
+    /// A function with this name is already registered.
+    AlreadyRegistered(&'static str),
+    /// The name isn't an identifier, so calls to the function couldn't be written in
+    /// the DSL.
+    InvalidName(&'static str),
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::BuiltinFunction(name) => {
+                write!(f, "`{name}` is a built-in key function")
+            }
+            RegistrationError::AlreadyRegistered(name) => {
+                write!(f, "a key function named `{name}` is already registered")
+            }
+            RegistrationError::InvalidName(name) => {
+                write!(f, "`{name}` is not a valid key function name")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+/// The registered key functions, by name.
+static KEY_FUNCTION_REGISTRY: LazyLock<RwLock<HashMap<&'static str, Arc<dyn KeyFunctionPlugin>>>> =
+    LazyLock::new(Default::default);
+
+/// Register a user-defined key function, so that calls to it parse as
+/// [`KeyFunction::Custom`](super::KeyFunction::Custom).
+pub fn register_key_function(
+    plugin: impl KeyFunctionPlugin + 'static,
+) -> Result<(), RegistrationError> {
+    let name = plugin.signature().name;
+    if !is_identifier(name) {
+        return Err(RegistrationError::InvalidName(name));
+    }
+    if KEY_FUNCTION_SIGNATURES.contains_key(name) {
+        return Err(RegistrationError::BuiltinFunction(name));
+    }
+    let mut registry = KEY_FUNCTION_REGISTRY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    if registry.contains_key(name) {
+        return Err(RegistrationError::AlreadyRegistered(name));
+    }
+    registry.insert(name, Arc::new(plugin));
+    Ok(())
+}
+
+/// Unregister a user-defined key function. Returns whether it was registered.
+pub fn unregister_key_function(function_name: &str) -> bool {
+    KEY_FUNCTION_REGISTRY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(function_name)
+        .is_some()
+}
+
+/// Look up a registered key function by name.
+pub fn registered_key_function(function_name: &str) -> Option<Arc<dyn KeyFunctionPlugin>> {
+    KEY_FUNCTION_REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(function_name)
+        .cloned()
+}
+
+/// The names of all registered key functions, in no particular order.
+pub fn registered_key_function_names() -> Vec<&'static str> {
+    KEY_FUNCTION_REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .keys()
+        .copied()
+        .collect()
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{
+        key_function::{CustomKeyFunction, UnknownKeyFunction},
+        key_parser::parse_key_definition,
+        key_signature::{check_key_definition, ArgumentKind, KeyType},
+        KeyFunction, KeyName,
+    };
+
+    /// `tld:key`; the top-level domain of a DNS name. Each test registers it under its
+    /// own name, since the registry is shared by all tests.
+    struct TldKeyFunction(&'static str);
+
+    impl KeyFunctionPlugin for TldKeyFunction {
+        fn signature(&self) -> KeyFunctionSignature {
+            KeyFunctionSignature {
+                name: self.0,
+                args: &[ArgumentKind::KeyName],
+                min_arity: 1,
+                max_arity: Some(1),
+                arg_types: &[],
+                result: KeyType::String,
+            }
+        }
+
+        fn evaluate(&self, args: &[&str]) -> Option<String> {
+            args.first()?.rsplit('.').next().map(String::from)
+        }
+    }
+
+    #[test]
+    fn test_register_key_function() {
+        register_key_function(TldKeyFunction("tld1")).expect("should register");
+        assert!(registered_key_function_names().contains(&"tld1"));
+        assert_eq!(
+            register_key_function(TldKeyFunction("tld1")),
+            Err(RegistrationError::AlreadyRegistered("tld1"))
+        );
+        assert_eq!(
+            register_key_function(TldKeyFunction("group")),
+            Err(RegistrationError::BuiltinFunction("group"))
+        );
+        for name in ["", "a:b", "x y", "tld[1]"] {
+            assert_eq!(
+                register_key_function(TldKeyFunction(name)),
+                Err(RegistrationError::InvalidName(name))
+            );
+            assert!(registered_key_function(name).is_none());
+        }
+        let plugin = registered_key_function("tld1").expect("should be registered");
+        assert_eq!(
+            plugin.evaluate(&["www.example.com"]),
+            Some("com".to_string())
+        );
+        assert!(unregister_key_function("tld1"));
+        assert!(!unregister_key_function("tld1"));
+        assert!(registered_key_function("tld1").is_none());
+    }
+
+    #[test]
+    fn test_parse_registered_key_function() {
+        register_key_function(TldKeyFunction("tld2")).expect("should register");
+        let (leftover, definition) =
+            parse_key_definition("tld2:ipsource,tld2:ipsource:ip6source").expect("should parse");
+        assert_eq!(leftover, "");
+        assert_eq!(
+            definition.keys,
+            vec![
+                KeyFunction::Custom(CustomKeyFunction {
+                    function_name: "tld2".to_string(),
+                    args: vec![KeyName::IpSource.into()],
+                })
+                .into(),
+                // too many arguments for the plugin's signature
+                KeyFunction::Unknown(UnknownKeyFunction {
+                    function_name: "tld2".to_string(),
+                    args: vec![KeyName::IpSource.into(), KeyName::Ip6Source.into()],
+                })
+                .into(),
+            ]
+        );
+        let errors: Vec<String> = check_key_definition(&definition)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(errors, vec!["tld2 expects at most 1 argument, found 2"]);
+        assert!(unregister_key_function("tld2"));
+    }
+}
@@ -0,0 +1,293 @@
+//! `serde` support for key definitions, behind the `serde` cargo feature.
+//!
+//! Key definitions implement `Serialize` and `Deserialize`, for a structured form that
+//! mirrors the AST, named as in the DSL: key names are written as they are in the DSL,
+//! and every key function call as its function's name and its arguments, whatever its
+//! variant. In JSON, `ipsource,country:ip6source` is:
+//!
+//! ```json
+//! {"keys":[{"key_name":"ipsource"},{"key_function":{"function":"country","args":[{"key_name":"ip6source"}]}}]}
+//! ```
+//!
+//! Deserializing accepts only what the parser could have produced from DSL text: a
+//! name that isn't an identifier, a call without arguments, a literal outside a call,
+//! or an empty key definition is an error. Each call is typed as the parser would type
+//! it (see [`KeyFunction::from_call`]), so its variant is never taken on trust; e.g. a
+//! `group` call whose arguments don't fit `group:` is an unknown key function call.
+//!
+//! For the compact form, the DSL text itself, use [`dsl`] on a field instead:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::KeyDefinition;
+//!
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! struct FlowConfig {
+//!     #[serde(with = "sflowrt_rs_flow::key::key_serde::dsl")]
+//!     keys: KeyDefinition,
+//! }
+//!
+//! let config: FlowConfig =
+//!     serde_json::from_str(r#"{"keys": "ipsource,country:ip6source"}"#).unwrap();
+//! assert_eq!(
+//!     serde_json::to_string(&config).unwrap(),
+//!     r#"{"keys":"ipsource,country:ip6source"}"#
+//! );
+//! ```
+
+use std::net::IpAddr;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::key_lexer::is_identifier;
+use super::key_literal::{AddressLiteral, Literal};
+use super::key_signature::{KEY_FUNCTION_NAME_COUNTRY, KEY_FUNCTION_NAME_GROUP};
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+impl<'de> Deserialize<'de> for KeyDefinition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            keys: Vec<KeyExpression>,
+        }
+
+        let Fields { keys } = Fields::deserialize(deserializer)?;
+        if keys.is_empty() {
+            return Err(D::Error::custom("a key definition needs a key expression"));
+        }
+        if keys
+            .iter()
+            .any(|key| matches!(key, KeyExpression::Literal(_)))
+        {
+            return Err(D::Error::custom(
+                "a literal can only be a key function argument",
+            ));
+        }
+        Ok(KeyDefinition::new(keys))
+    }
+}
+
+/// Writes the key name as it is written in the DSL.
+impl Serialize for KeyName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_identifier())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        if !is_identifier(&name) {
+            return Err(D::Error::custom(format!("`{name}` is not a key name")));
+        }
+        Ok(KeyName::from(name.as_str()))
+    }
+}
+
+/// A key function call as it is (de)serialized: its function's name, and its
+/// arguments as they would be written in the DSL.
+#[derive(Serialize, Deserialize)]
+struct KeyFunctionCall {
+    function: String,
+    args: Vec<KeyExpression>,
+}
+
+impl From<&KeyFunction> for KeyFunctionCall {
+    fn from(function: &KeyFunction) -> Self {
+        let (function, args) = match function {
+            KeyFunction::Group(group) => (
+                KEY_FUNCTION_NAME_GROUP.to_string(),
+                std::iter::once((*group.key).clone())
+                    .chain(
+                        group
+                            .group_names
+                            .iter()
+                            .map(|name| Literal::String(name.clone()).into()),
+                    )
+                    .collect(),
+            ),
+            KeyFunction::Country(country) => (
+                KEY_FUNCTION_NAME_COUNTRY.to_string(),
+                vec![KeyName::from(country.arg.as_str()).into()],
+            ),
+            KeyFunction::Custom(custom) => (custom.function_name.clone(), custom.args.clone()),
+            KeyFunction::Unknown(unknown) => (unknown.function_name.clone(), unknown.args.clone()),
+        };
+        KeyFunctionCall { function, args }
+    }
+}
+
+impl Serialize for KeyFunction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        KeyFunctionCall::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyFunction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let KeyFunctionCall { function, args } = KeyFunctionCall::deserialize(deserializer)?;
+        if !is_identifier(&function) {
+            return Err(D::Error::custom(format!(
+                "`{function}` is not a key function name"
+            )));
+        }
+        if args.is_empty() {
+            return Err(D::Error::custom(format!(
+                "a call to `{function}` needs an argument"
+            )));
+        }
+        Ok(KeyFunction::from_call(function, args))
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressLiteral {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            address: IpAddr,
+            prefix_len: Option<u8>,
+        }
+
+        let Fields {
+            address,
+            prefix_len,
+        } = Fields::deserialize(deserializer)?;
+        let literal = AddressLiteral {
+            address,
+            prefix_len,
+        };
+        if prefix_len.is_some_and(|prefix_len| prefix_len > literal.max_prefix_len()) {
+            return Err(D::Error::custom(format!(
+                "`{literal}` has too long a prefix"
+            )));
+        }
+        Ok(literal)
+    }
+}
+
+/// (De)serialize a value as its DSL text, via its `Display` and `FromStr`
+/// implementations; for use with `#[serde(with = "...")]`. Deserializing fails if the
+/// text doesn't parse.
+pub mod dsl {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display,
+        S: Serializer,
+    {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(D::Error::custom)
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::key::KeyDefinition;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Compact {
+        #[serde(with = "super::dsl")]
+        keys: KeyDefinition,
+    }
+
+    const INPUT: &str = r#"ipsource,group:[country:ip6source]:"a,b",f:{10.0.0.0/8}:-1:/x/"#;
+
+    #[test]
+    fn test_structured_round_trip() {
+        let definition: KeyDefinition = INPUT.parse().expect("should parse");
+        let json = serde_json::to_string(&definition).expect("should serialize");
+        assert!(
+            json.starts_with(
+                r#"{"keys":[{"key_name":"ipsource"},{"key_function":{"function":"group","#
+            ),
+            "{json}"
+        );
+        let deserialized: KeyDefinition = serde_json::from_str(&json).expect("should deserialize");
+        assert_eq!(deserialized, definition);
+    }
+
+    /// Each call is typed as the parser would type the same call in the DSL.
+    #[rstest::rstest]
+    #[case(r#"{"key_name":"ipsource"}"#, "ipsource")]
+    #[case(r#"{"key_name":"IpSource"}"#, "IpSource")]
+    #[case(
+        r#"{"key_function":{"function":"group","args":[{"key_name":"ipsource"},{"key_name":"a"}]}}"#,
+        "group:ipsource:a"
+    )]
+    #[case(
+        r#"{"key_function":{"function":"group","args":[{"key_name":"ipsource"}]}}"#,
+        "group:ipsource"
+    )]
+    #[case(
+        r#"{"key_function":{"function":"country","args":[{"literal":{"integer":1}}]}}"#,
+        "country:1"
+    )]
+    fn test_structured_matches_parser(#[case] json: &str, #[case] dsl: &str) {
+        let json = format!(r#"{{"keys":[{json}]}}"#);
+        let deserialized: KeyDefinition = serde_json::from_str(&json).expect("should deserialize");
+        assert_eq!(Ok(deserialized), dsl.parse());
+    }
+
+    #[rstest::rstest]
+    #[case(r#"{"keys":[]}"#, "a key definition needs a key expression")]
+    #[case(r#"{"keys":[{"KeyName":"IpSource"}]}"#, "unknown variant `KeyName`")]
+    #[case(r#"{"keys":[{"key_name":"a b"}]}"#, "`a b` is not a key name")]
+    #[case(
+        r#"{"keys":[{"literal":{"integer":1}}]}"#,
+        "a literal can only be a key function argument"
+    )]
+    #[case(
+        r#"{"keys":[{"key_function":{"function":"a:b","args":[{"key_name":"c"}]}}]}"#,
+        "`a:b` is not a key function name"
+    )]
+    #[case(
+        r#"{"keys":[{"key_function":{"function":"f","args":[]}}]}"#,
+        "a call to `f` needs an argument"
+    )]
+    #[case(
+        r#"{"keys":[{"key_function":{"function":"f","args":[{"literal":{"address":{"address":"10.0.0.0","prefix_len":33}}}]}}]}"#,
+        "has too long a prefix"
+    )]
+    fn test_structured_invalid(#[case] json: &str, #[case] message: &str) {
+        let error =
+            serde_json::from_str::<KeyDefinition>(json).expect_err("should fail to deserialize");
+        assert!(error.to_string().contains(message), "{error}");
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let compact = Compact {
+            keys: INPUT.parse().expect("should parse"),
+        };
+        let json = serde_json::to_string(&compact).expect("should serialize");
+        assert_eq!(json, serde_json::json!({ "keys": INPUT }).to_string());
+        let deserialized: Compact = serde_json::from_str(&json).expect("should deserialize");
+        assert_eq!(deserialized, compact);
+    }
+
+    #[test]
+    fn test_compact_invalid() {
+        let error = serde_json::from_str::<Compact>(r#"{"keys": "ipsource;ip6source"}"#)
+            .expect_err("should fail to deserialize");
+        assert!(
+            error
+                .to_string()
+                .starts_with("expected `,` or end of input at byte 8"),
+            "{error}"
+        );
+    }
+}
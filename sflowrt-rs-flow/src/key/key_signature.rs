@@ -0,0 +1,543 @@
+//! Declarative signatures for the known sFlow-RT key functions, and a checker that
+//! validates key function calls against them.
+//!
+//! See [sFlow-RT's documentation on Key Functions](https://sflow-rt.com/define_flow.php#keyfunctions).
+
+use std::fmt;
+
+use super::key_function::{CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction};
+use super::key_literal::LiteralKind;
+use super::key_registry::{registered_key_function, KeyFunctionPlugin};
+use super::key_span::KeyFunctionKind;
+use super::key_visit::{self, Visit};
+use super::{KeyDefinition, KeyExpression};
+
+/// The kind of a single key function argument, as written in the key definition DSL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgumentKind {
+    /// Any key expression: a plain key name, or a nested (bracketed) key function.
+    KeyExpression,
+    /// A plain key name; nested key functions are not accepted.
+    KeyName,
+    /// A group name, as used by `group:`: an identifier or a string literal.
+    GroupName,
+    /// A literal of the given kind.
+    Literal(LiteralKind),
+}
+
+impl ArgumentKind {
+    /// Whether an argument, as parsed from the DSL, is acceptable for this kind.
+    pub fn accepts(&self, arg: &KeyExpression) -> bool {
+        self.accepts_argument(arg)
+    }
+
+    fn accepts_argument(&self, arg: &impl CallArgument) -> bool {
+        match self {
+            ArgumentKind::KeyExpression => arg.literal_kind().is_none(),
+            ArgumentKind::KeyName => arg.is_key_name(),
+            ArgumentKind::GroupName => {
+                arg.is_key_name() || arg.literal_kind() == Some(LiteralKind::String)
+            }
+            ArgumentKind::Literal(kind) => arg.literal_kind() == Some(*kind),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ArgumentKind::KeyExpression => "key expression",
+            ArgumentKind::KeyName => "key name",
+            ArgumentKind::GroupName => "group name",
+            ArgumentKind::Literal(kind) => kind.description(),
+        }
+    }
+
+    fn description_with_article(&self) -> &'static str {
+        match self {
+            ArgumentKind::KeyExpression => "a key expression",
+            ArgumentKind::KeyName => "a key name",
+            ArgumentKind::GroupName => "a group name",
+            ArgumentKind::Literal(kind) => kind.description_with_article(),
+        }
+    }
+}
+
+/// The type of a key's values, or of the values a key function produces; see
+/// [`super::key_type`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// A string value, such as a group name or a country code.
+    String,
+    /// An integer, such as a TTL or a DSCP code point.
+    Integer,
+    /// A boolean flag, such as the IPv6 fragment m flag.
+    Boolean,
+    /// A string of bits, such as the IPv6 type of service bits (`01100000`).
+    Bits,
+    Ipv4Address,
+    Ipv6Address,
+    /// A list of values, such as the next header values of IPv6 extension headers.
+    List,
+}
+
+impl KeyType {
+    pub fn is_address(&self) -> bool {
+        matches!(self, KeyType::Ipv4Address | KeyType::Ipv6Address)
+    }
+
+    pub(crate) fn description_with_article(&self) -> &'static str {
+        match self {
+            KeyType::String => "a string",
+            KeyType::Integer => "an integer",
+            KeyType::Boolean => "a boolean",
+            KeyType::Bits => "a bit string",
+            KeyType::Ipv4Address => "an IPv4 address",
+            KeyType::Ipv6Address => "an IPv6 address",
+            KeyType::List => "a list",
+        }
+    }
+}
+
+/// The types of value a key function accepts for one of its key arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeConstraint {
+    /// A value of any type.
+    Any,
+    /// A value of exactly this type.
+    Exactly(KeyType),
+    /// An IPv4 or IPv6 address.
+    Address,
+}
+
+impl TypeConstraint {
+    pub fn accepts(&self, key_type: KeyType) -> bool {
+        match self {
+            TypeConstraint::Any => true,
+            TypeConstraint::Exactly(expected) => *expected == key_type,
+            TypeConstraint::Address => key_type.is_address(),
+        }
+    }
+
+    pub(crate) fn description_with_article(&self) -> &'static str {
+        match self {
+            TypeConstraint::Any => "a value of any type",
+            TypeConstraint::Exactly(key_type) => key_type.description_with_article(),
+            TypeConstraint::Address => "an IP address",
+        }
+    }
+}
+
+/// The signature of a key function: its name, the kinds of arguments it expects, its
+/// arity, and the type of value it produces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyFunctionSignature {
+    /// The function's name in the DSL, e.g. `"group"`.
+    pub name: &'static str,
+    /// The kinds of the function's arguments, in order. If the function accepts more
+    /// arguments than are listed here, the extra arguments take the last kind.
+    pub args: &'static [ArgumentKind],
+    /// The minimum number of arguments.
+    pub min_arity: usize,
+    /// The maximum number of arguments, or `None` if the function is variadic.
+    pub max_arity: Option<usize>,
+    /// The types of the function's key arguments, position by position as for
+    /// `args`. An argument with no type listed (e.g. if this is empty) may have any
+    /// type.
+    pub arg_types: &'static [TypeConstraint],
+    /// The type of value the function produces.
+    pub result: KeyType,
+}
+
+impl KeyFunctionSignature {
+    /// The expected kind of the argument at (zero-based) `position`.
+    pub fn arg_kind(&self, position: usize) -> Option<ArgumentKind> {
+        if self.max_arity.is_some_and(|max| position >= max) {
+            return None;
+        }
+        self.args.get(position).or(self.args.last()).copied()
+    }
+
+    /// The type constraint on the key argument at (zero-based) `position`.
+    pub fn arg_type(&self, position: usize) -> TypeConstraint {
+        if self.max_arity.is_some_and(|max| position >= max) {
+            return TypeConstraint::Any;
+        }
+        self.arg_types
+            .get(position)
+            .or(self.arg_types.last())
+            .copied()
+            .unwrap_or(TypeConstraint::Any)
+    }
+
+    /// Check an argument count against this signature's arity.
+    pub fn check_arity(&self, n_args: usize) -> Result<(), SignatureError> {
+        if n_args < self.min_arity {
+            let position = n_args;
+            return Err(SignatureError::TooFewArguments {
+                function: self.name,
+                expected: self
+                    .arg_kind(position)
+                    .unwrap_or(ArgumentKind::KeyExpression),
+                position,
+                variadic: self.max_arity.is_none() && position + 1 >= self.args.len(),
+            });
+        }
+        match self.max_arity {
+            Some(max) if n_args > max => Err(SignatureError::TooManyArguments {
+                function: self.name,
+                max,
+                found: n_args,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a list of arguments, as parsed from the DSL, against this signature.
+    pub fn check_args(&self, args: &[KeyExpression]) -> Result<(), SignatureError> {
+        self.check_call_args(args)
+    }
+
+    fn check_call_args(&self, args: &[impl CallArgument]) -> Result<(), SignatureError> {
+        self.check_arity(args.len())?;
+        for (position, arg) in args.iter().enumerate() {
+            if let Some(expected) = self.arg_kind(position) {
+                if !expected.accepts_argument(arg) {
+                    return Err(SignatureError::WrongArgumentKind {
+                        function: self.name,
+                        position,
+                        expected,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of the `group:` key function.
+pub const KEY_FUNCTION_NAME_GROUP: &str = "group";
+/// The name of the `country:` key function.
+pub const KEY_FUNCTION_NAME_COUNTRY: &str = "country";
+/// The name of the `or:` key function.
+pub const KEY_FUNCTION_NAME_OR: &str = "or";
+/// The name of the `mask:` key function.
+pub const KEY_FUNCTION_NAME_MASK: &str = "mask";
+
+/// A hashmap from the sFlow-RT key function name to the function's signature, for the
+/// built-in key functions.
+pub static KEY_FUNCTION_SIGNATURES: phf::Map<&'static str, KeyFunctionSignature> = phf::phf_map! {
+    // group:key:group1:group2:...
+    "group" => KeyFunctionSignature {
+        name: KEY_FUNCTION_NAME_GROUP,
+        args: &[ArgumentKind::KeyExpression, ArgumentKind::GroupName],
+        min_arity: 2,
+        max_arity: None,
+        arg_types: &[],
+        result: KeyType::String,
+    },
+    // country:key, where key is an IP address
+    "country" => KeyFunctionSignature {
+        name: KEY_FUNCTION_NAME_COUNTRY,
+        args: &[ArgumentKind::KeyName],
+        min_arity: 1,
+        max_arity: Some(1),
+        arg_types: &[TypeConstraint::Address],
+        result: KeyType::String,
+    },
+    // mask:key:prefix_len, where key is an IP address
+    "mask" => KeyFunctionSignature {
+        name: KEY_FUNCTION_NAME_MASK,
+        args: &[
+            ArgumentKind::KeyExpression,
+            ArgumentKind::Literal(LiteralKind::Integer),
+        ],
+        min_arity: 2,
+        max_arity: Some(2),
+        arg_types: &[TypeConstraint::Address],
+        result: KeyType::String,
+    },
+    // or:key1:key2:..., the first of the keys that has a value; its result is really the
+    // type of its keys, see KeyFunction::result_type
+    "or" => KeyFunctionSignature {
+        name: KEY_FUNCTION_NAME_OR,
+        args: &[ArgumentKind::KeyExpression],
+        min_arity: 1,
+        max_arity: None,
+        arg_types: &[],
+        result: KeyType::String,
+    },
+};
+
+/// Look up the signature of a known key function by its DSL name. Known functions are
+/// the built-in ones, plus any registered via
+/// [`register_key_function`](super::key_registry::register_key_function).
+pub fn key_function_signature(function_name: &str) -> Option<KeyFunctionSignature> {
+    KEY_FUNCTION_SIGNATURES
+        .get(function_name)
+        .copied()
+        .or_else(|| registered_key_function(function_name).map(|plugin| plugin.signature()))
+}
+
+/// An argument to a key function call, owned or borrowed: what [`type_call`] needs to
+/// know about it.
+pub(crate) trait CallArgument: Sized {
+    /// The kind of literal the argument is, or `None` if it isn't a literal.
+    fn literal_kind(&self) -> Option<LiteralKind>;
+
+    fn is_key_name(&self) -> bool;
+
+    /// Whether `plugin` accepts `args`; see [`KeyFunctionPlugin::parse_arguments`].
+    fn are_accepted_by(plugin: &dyn KeyFunctionPlugin, args: &[Self]) -> bool;
+}
+
+impl CallArgument for KeyExpression {
+    fn literal_kind(&self) -> Option<LiteralKind> {
+        match self {
+            KeyExpression::Literal(literal) => Some(literal.kind()),
+            _ => None,
+        }
+    }
+
+    fn is_key_name(&self) -> bool {
+        matches!(self, KeyExpression::KeyName(_))
+    }
+
+    fn are_accepted_by(plugin: &dyn KeyFunctionPlugin, args: &[Self]) -> bool {
+        plugin.parse_arguments(args).is_ok()
+    }
+}
+
+/// The variant a call to `function_name` with `args` is typed as, by both
+/// [`KeyFunction::from_call`](super::KeyFunction::from_call) and its borrowed
+/// counterpart: a built-in or registered function whose arguments match its signature
+/// (and, for a registered one, that its plugin accepts) gets its own variant, and
+/// anything else is unknown.
+pub(crate) fn type_call<A: CallArgument>(function_name: &str, args: &[A]) -> KeyFunctionKind {
+    let matches_signature = key_function_signature(function_name)
+        .is_some_and(|signature| signature.check_call_args(args).is_ok());
+    if !matches_signature {
+        return KeyFunctionKind::Unknown;
+    }
+    match function_name {
+        KEY_FUNCTION_NAME_GROUP => KeyFunctionKind::Group,
+        KEY_FUNCTION_NAME_COUNTRY => KeyFunctionKind::Country,
+        _ if KEY_FUNCTION_SIGNATURES.contains_key(function_name) => KeyFunctionKind::Custom,
+        _ if registered_key_function(function_name)
+            .is_some_and(|plugin| A::are_accepted_by(plugin.as_ref(), args)) =>
+        {
+            KeyFunctionKind::Custom
+        }
+        _ => KeyFunctionKind::Unknown,
+    }
+}
+
+/// A key function call that does not match its function's signature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Fewer arguments were given than the function requires.
+    TooFewArguments {
+        function: &'static str,
+        /// The kind of the first missing argument.
+        expected: ArgumentKind,
+        /// The (zero-based) position of the first missing argument.
+        position: usize,
+        /// Whether the missing argument is the function's repeated, trailing one.
+        variadic: bool,
+    },
+    /// More arguments were given than the function accepts.
+    TooManyArguments {
+        function: &'static str,
+        max: usize,
+        found: usize,
+    },
+    /// An argument was of a kind the function does not accept at that position.
+    WrongArgumentKind {
+        function: &'static str,
+        /// The (zero-based) position of the offending argument.
+        position: usize,
+        expected: ArgumentKind,
+    },
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::TooFewArguments {
+                function,
+                expected,
+                variadic: true,
+                ..
+            } => write!(
+                f,
+                "{function} expects at least one {}",
+                expected.description()
+            ),
+            SignatureError::TooFewArguments {
+                function,
+                expected,
+                position,
+                variadic: false,
+            } => write!(
+                f,
+                "{function} expects {} as argument {}",
+                expected.description_with_article(),
+                position + 1
+            ),
+            SignatureError::TooManyArguments {
+                function,
+                max,
+                found,
+            } => {
+                let plural = if *max == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "{function} expects at most {max} argument{plural}, found {found}"
+                )
+            }
+            SignatureError::WrongArgumentKind {
+                function,
+                position,
+                expected,
+            } => write!(
+                f,
+                "{function} expects {} as argument {}",
+                expected.description_with_article(),
+                position + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+// checker ////////////////////////////////////////////////////////////////////////////
+
+/// Check every key function call in a key definition against the known signatures.
+///
+/// Typed key functions are checked for arity only, since their argument kinds are
+/// guaranteed by construction. Calls to registered functions, and calls that the
+/// parser could not type (and so produced as
+/// [`KeyFunction::Unknown`](super::KeyFunction::Unknown)) but that name a known
+/// function, are checked in full;
+/// this is what explains *why*, for example, `group:ipsource` is not a valid `group:`
+/// call. Calls to functions with no known signature are not checked.
+pub fn check_key_definition(definition: &KeyDefinition) -> Vec<SignatureError> {
+    let mut checker = SignatureChecker::default();
+    checker.visit_key_definition(definition);
+    checker.errors
+}
+
+/// Check every key function call in a key expression against the known signatures.
+///
+/// See [`check_key_definition`].
+pub fn check_key_expression(expression: &KeyExpression) -> Vec<SignatureError> {
+    let mut checker = SignatureChecker::default();
+    checker.visit_key_expression(expression);
+    checker.errors
+}
+
+#[derive(Default)]
+struct SignatureChecker {
+    errors: Vec<SignatureError>,
+}
+
+impl SignatureChecker {
+    fn check_call(&mut self, function_name: &str, args: &[KeyExpression]) {
+        if let Some(signature) = key_function_signature(function_name) {
+            self.errors.extend(signature.check_args(args).err());
+        }
+    }
+}
+
+impl Visit for SignatureChecker {
+    fn visit_group_key_function(&mut self, group: &GroupKeyFunction) {
+        if let Some(signature) = key_function_signature(KEY_FUNCTION_NAME_GROUP) {
+            self.errors
+                .extend(signature.check_arity(1 + group.group_names.len()).err());
+        }
+        key_visit::visit_group_key_function(self, group);
+    }
+
+    fn visit_custom_key_function(&mut self, custom: &CustomKeyFunction) {
+        self.check_call(&custom.function_name, &custom.args);
+        key_visit::visit_custom_key_function(self, custom);
+    }
+
+    fn visit_unknown_key_function(&mut self, unknown: &UnknownKeyFunction) {
+        self.check_call(&unknown.function_name, &unknown.args);
+        key_visit::visit_unknown_key_function(self, unknown);
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{key_parser::parse_key_definition, KeyFunction, KeyName};
+
+    fn check(input: &str) -> Vec<String> {
+        let (leftover, definition) = parse_key_definition(input).expect("should parse");
+        assert_eq!(leftover, "", "should parse fully");
+        check_key_definition(&definition)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_signatures_are_consistent() {
+        for (name, signature) in KEY_FUNCTION_SIGNATURES.entries() {
+            assert_eq!(*name, signature.name);
+            assert!(!signature.args.is_empty(), "{name} should take arguments");
+            assert!(
+                signature.min_arity >= 1,
+                "{name} should require an argument"
+            );
+            if let Some(max) = signature.max_arity {
+                assert!(signature.min_arity <= max, "{name} has min arity > max");
+            }
+            assert!(
+                signature.arg_types.len() <= signature.args.len(),
+                "{name} has more argument types than arguments"
+            );
+        }
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource", &[])]
+    #[case("group:ipsource:trusted", &[])]
+    #[case("group:[country:ip6source]:a:b:c", &[])]
+    #[case("country:ipsource", &[])]
+    #[case("unknownfunc:ipsource:anything", &[])]
+    #[case(r#"group:ipsource:"my group":b"#, &[])]
+    #[case("group:24:trusted", &["group expects a key expression as argument 1"])]
+    #[case("group:ipsource:/^a/", &["group expects a group name as argument 2"])]
+    #[case("country:{10.0.0.0/8}", &["country expects a key name as argument 1"])]
+    #[case("group:ipsource", &["group expects at least one group name"])]
+    #[case("group:ipsource:[country:ipsource]", &["group expects a group name as argument 2"])]
+    #[case("country:[country:ipsource]", &["country expects a key name as argument 1"])]
+    #[case("country:ipsource:ip6source", &["country expects at most 1 argument, found 2"])]
+    #[case(
+        "ipsource,group:ipsource,unknownfunc:[group:ipsource]",
+        &["group expects at least one group name", "group expects at least one group name"]
+    )]
+    fn test_check_key_definition(#[case] input: &str, #[case] expected: &[&str]) {
+        assert_eq!(check(input), expected);
+    }
+
+    #[test]
+    fn test_check_typed_group_arity() {
+        let group = KeyFunction::from(GroupKeyFunction {
+            key: Box::new(KeyExpression::KeyName(KeyName::IpSource)),
+            group_names: vec![],
+        });
+        assert_eq!(
+            check_key_expression(&group.into()),
+            vec![SignatureError::TooFewArguments {
+                function: KEY_FUNCTION_NAME_GROUP,
+                expected: ArgumentKind::GroupName,
+                position: 1,
+                variadic: true,
+            }]
+        );
+    }
+}
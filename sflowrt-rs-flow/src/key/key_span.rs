@@ -0,0 +1,274 @@
+//! Source spans for key definitions.
+//!
+//! The AST in [`super`] doesn't record where its nodes came from, so that nodes compare
+//! equal however they were written. For editor tooling and diagnostics,
+//! [`parse_key_definition_spanned`](super::key_parser::parse_key_definition_spanned)
+//! produces a parallel tree, in which every key expression, key name, key function
+//! (and its name), and argument has the byte range of the text it was parsed from.
+//! [`SpannedKeyDefinition::to_key_definition`] drops the spans again.
+
+use std::ops::Range;
+
+use super::key_function::{
+    CountryKeyFunction, CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction,
+};
+use super::key_literal::{Literal, LiteralKind};
+use super::key_registry::KeyFunctionPlugin;
+use super::key_signature::CallArgument;
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// A byte range in the source text of a key definition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    /// The byte offset of the start of the span.
+    pub start: usize,
+    /// The byte offset just past the end of the span.
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether the byte at `offset` is in this span. An empty span contains its start.
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.start..self.end.max(self.start + 1)).contains(&offset)
+    }
+
+    /// The text of this span in `source`, the text it was parsed from.
+    pub fn slice<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start..span.end
+    }
+}
+
+/// Which [`KeyFunction`] variant a spanned key function call was parsed as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyFunctionKind {
+    Group,
+    Country,
+    Custom,
+    Unknown,
+}
+
+impl From<&KeyFunction> for KeyFunctionKind {
+    fn from(function: &KeyFunction) -> Self {
+        match function {
+            KeyFunction::Group(_) => KeyFunctionKind::Group,
+            KeyFunction::Country(_) => KeyFunctionKind::Country,
+            KeyFunction::Custom(_) => KeyFunctionKind::Custom,
+            KeyFunction::Unknown(_) => KeyFunctionKind::Unknown,
+        }
+    }
+}
+
+/// A [`KeyDefinition`] with source spans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedKeyDefinition {
+    pub keys: Vec<SpannedKeyExpression>,
+}
+
+impl SpannedKeyDefinition {
+    pub fn to_key_definition(&self) -> KeyDefinition {
+        KeyDefinition {
+            keys: self
+                .keys
+                .iter()
+                .map(SpannedKeyExpression::to_key_expression)
+                .collect(),
+        }
+    }
+
+    /// The innermost key expression or argument at byte `offset`, if any.
+    pub fn find_at(&self, offset: usize) -> Option<&SpannedKeyExpression> {
+        self.keys.iter().find_map(|key| key.find_at(offset))
+    }
+}
+
+/// A [`KeyExpression`], or a key function argument, with its source span.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpannedKeyExpression {
+    KeyName { key_name: KeyName, span: Span },
+    KeyFunction(SpannedKeyFunction),
+    Literal { literal: Literal, span: Span },
+}
+
+impl SpannedKeyExpression {
+    /// The span of this expression. For a nested key function argument, this
+    /// excludes the surrounding brackets.
+    pub fn span(&self) -> Span {
+        match self {
+            SpannedKeyExpression::KeyName { span, .. }
+            | SpannedKeyExpression::Literal { span, .. } => *span,
+            SpannedKeyExpression::KeyFunction(function) => function.span,
+        }
+    }
+
+    pub fn to_key_expression(&self) -> KeyExpression {
+        match self {
+            SpannedKeyExpression::KeyName { key_name, .. } => key_name.clone().into(),
+            SpannedKeyExpression::KeyFunction(function) => function.to_key_function().into(),
+            SpannedKeyExpression::Literal { literal, .. } => literal.clone().into(),
+        }
+    }
+
+    /// The innermost expression at byte `offset` (this one, or one of its arguments),
+    /// if any.
+    pub fn find_at(&self, offset: usize) -> Option<&SpannedKeyExpression> {
+        if !self.span().contains(offset) {
+            return None;
+        }
+        if let SpannedKeyExpression::KeyFunction(function) = self {
+            if let Some(arg) = function.args.iter().find_map(|arg| arg.find_at(offset)) {
+                return Some(arg);
+            }
+        }
+        Some(self)
+    }
+}
+
+/// Checking a call to a registered key function converts its arguments to unspanned
+/// ones, for the plugin.
+impl CallArgument for SpannedKeyExpression {
+    fn literal_kind(&self) -> Option<LiteralKind> {
+        match self {
+            SpannedKeyExpression::Literal { literal, .. } => Some(literal.kind()),
+            _ => None,
+        }
+    }
+
+    fn is_key_name(&self) -> bool {
+        matches!(self, SpannedKeyExpression::KeyName { .. })
+    }
+
+    fn are_accepted_by(plugin: &dyn KeyFunctionPlugin, args: &[Self]) -> bool {
+        let args: Vec<KeyExpression> = args
+            .iter()
+            .map(SpannedKeyExpression::to_key_expression)
+            .collect();
+        plugin.parse_arguments(&args).is_ok()
+    }
+}
+
+/// A [`KeyFunction`] call with source spans. Its arguments are kept as they were
+/// written, whatever the function; e.g. `group:`'s group names are among its `args`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedKeyFunction {
+    /// The variant the call was parsed as.
+    pub kind: KeyFunctionKind,
+    pub function_name: String,
+    pub name_span: Span,
+    pub args: Vec<SpannedKeyExpression>,
+    /// The span of the whole call, from its name to the end of its last argument.
+    pub span: Span,
+}
+
+impl SpannedKeyFunction {
+    /// The call without spans. A `group:` or `country:` call whose arguments have
+    /// been changed so that they no longer fit the function becomes an unknown call.
+    pub fn to_key_function(&self) -> KeyFunction {
+        let args: Vec<KeyExpression> = self
+            .args
+            .iter()
+            .map(SpannedKeyExpression::to_key_expression)
+            .collect();
+        match self.kind {
+            KeyFunctionKind::Group => {
+                if let Some(group) = GroupKeyFunction::from_arguments(args.clone()) {
+                    return group.into();
+                }
+            }
+            KeyFunctionKind::Country => {
+                if let Some(country) = CountryKeyFunction::from_arguments(args.clone()) {
+                    return country.into();
+                }
+            }
+            KeyFunctionKind::Custom => {
+                return CustomKeyFunction {
+                    function_name: self.function_name.clone(),
+                    args,
+                }
+                .into();
+            }
+            KeyFunctionKind::Unknown => {}
+        }
+        UnknownKeyFunction {
+            function_name: self.function_name.clone(),
+            args,
+        }
+        .into()
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::key_parser::{parse_key_definition_complete, parse_key_definition_spanned};
+
+    #[rstest::rstest]
+    #[case("ipsource")]
+    #[case("ipsource,country:ip6source")]
+    #[case("group:[country:ip6source]:trusted:\"a,b\"")]
+    #[case("unknownfunc:ipsource:[group:ipsource:a]:24:{fe80::/10}:/x\\/y/")]
+    #[case("group:ipsource,country:[country:ipsource]")]
+    fn test_spanned_matches_unspanned(#[case] input: &str) {
+        let spanned = parse_key_definition_spanned(input).expect("should parse");
+        assert_eq!(
+            Ok(spanned.to_key_definition()),
+            parse_key_definition_complete(input)
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "ipsource,group:[country:ip6source]:\"a b\"";
+        let spanned = parse_key_definition_spanned(input).expect("should parse");
+        let spans: Vec<&str> = spanned
+            .keys
+            .iter()
+            .map(|key| key.span().slice(input))
+            .collect();
+        assert_eq!(spans, vec!["ipsource", "group:[country:ip6source]:\"a b\""]);
+
+        let SpannedKeyExpression::KeyFunction(group) = &spanned.keys[1] else {
+            panic!("expected a key function, got {:?}", spanned.keys[1]);
+        };
+        assert_eq!(group.kind, KeyFunctionKind::Group);
+        assert_eq!(group.name_span.slice(input), "group");
+        let args: Vec<&str> = group
+            .args
+            .iter()
+            .map(|arg| arg.span().slice(input))
+            .collect();
+        assert_eq!(args, vec!["country:ip6source", "\"a b\""]);
+    }
+
+    #[test]
+    fn test_find_at() {
+        let input = "ipsource,group:[country:ip6source]:trusted";
+        let spanned = parse_key_definition_spanned(input).expect("should parse");
+        let found = |offset| spanned.find_at(offset).map(|e| e.span().slice(input));
+        assert_eq!(found(0), Some("ipsource"));
+        // the separator between key expressions
+        assert_eq!(found(8), None);
+        assert_eq!(found(9), Some("group:[country:ip6source]:trusted"));
+        assert_eq!(found(25), Some("ip6source"));
+        assert_eq!(found(input.len() - 1), Some("trusted"));
+        assert_eq!(found(input.len()), None);
+    }
+}
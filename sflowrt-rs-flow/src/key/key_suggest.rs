@@ -0,0 +1,244 @@
+//! "Did you mean" suggestions for unknown key names and key functions.
+//!
+//! The parser is lenient: a misspelled key like `ip6sorce` or function like `grup`
+//! parses as [`KeyName::Unknown`] or [`KeyFunction::Unknown`](super::KeyFunction::Unknown).
+//! This module finds those nodes in a parsed [`KeyDefinition`] and suggests the closest
+//! known names.
+
+use std::fmt;
+
+use super::key_function::{CustomKeyFunction, UnknownKeyFunction};
+use super::key_registry::registered_key_function_names;
+use super::key_signature::{key_function_signature, ArgumentKind, KEY_FUNCTION_SIGNATURES};
+use super::key_visit::Visit;
+use super::{KeyDefinition, KeyExpression, KeyName, KEY_NAME_TO_VARIANT};
+
+/// The maximum number of suggestions given for a single unknown name.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// What kind of name an [`UnknownName`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownNameKind {
+    /// An unknown key name.
+    Key,
+    /// An unknown key function name.
+    Function,
+}
+
+/// An unknown key or key function name found in a key definition, with the closest
+/// known names (if any are close enough to be plausible).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownName {
+    pub kind: UnknownNameKind,
+    pub name: String,
+    /// The known names closest to `name`, in alphabetical order.
+    pub suggestions: Vec<&'static str>,
+}
+
+impl fmt::Display for UnknownName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            UnknownNameKind::Key => "key",
+            UnknownNameKind::Function => "key function",
+        };
+        write!(f, "unknown {kind} `{}`", self.name)?;
+        match self.suggestions.as_slice() {
+            [] => Ok(()),
+            [only] => write!(f, "; did you mean `{only}`?"),
+            suggestions => {
+                let suggestions: Vec<_> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+                write!(f, "; did you mean one of {}?", suggestions.join(", "))
+            }
+        }
+    }
+}
+
+/// Suggest the known sFlow-RT key names closest to `name`.
+pub fn suggest_key_names(name: &str) -> Vec<&'static str> {
+    suggest(name, KEY_NAME_TO_VARIANT.keys().copied())
+}
+
+/// Suggest the known sFlow-RT key function names closest to `name`, including those
+/// of registered key functions.
+pub fn suggest_key_function_names(name: &str) -> Vec<&'static str> {
+    let registered = registered_key_function_names();
+    suggest(
+        name,
+        KEY_FUNCTION_SIGNATURES.keys().copied().chain(registered),
+    )
+}
+
+fn suggest(name: &str, candidates: impl Iterator<Item = &'static str>) -> Vec<&'static str> {
+    let name = name.to_lowercase();
+    // allow roughly one edit per three characters, but always at least one
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut scored: Vec<(usize, &'static str)> = candidates
+        .map(|candidate| (edit_distance(&name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    scored.sort_unstable();
+    // only the closest candidates are suggested; `ip6sorce` is one edit from
+    // `ip6source` and two from `ipsource`, and suggesting both is just noise
+    let closest = scored.first().map(|(distance, _)| *distance);
+    scored
+        .into_iter()
+        .take_while(|(distance, _)| Some(*distance) == closest)
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// The optimal string alignment distance between `a` and `b`: the Levenshtein
+/// distance, but also counting a transposition of two adjacent characters as a
+/// single edit (a common typo, e.g. `ipsoucre`).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows `i - 2`, `i - 1` and `i` of the dynamic programming table
+    let mut prev_prev: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        curr[0] = i;
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            curr[j] = (prev[j] + 1)
+                .min(curr[j - 1] + 1)
+                .min(prev[j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                curr[j] = curr[j].min(prev_prev[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev_prev, &mut prev);
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+// analysis ///////////////////////////////////////////////////////////////////////////
+
+/// Find every unknown key name and key function name in a key definition, in the
+/// order they appear, with suggestions for each.
+///
+/// Arguments to a known key function that are not key names (e.g. `group:`'s group
+/// names) are not reported. A call to a known function that did not match its
+/// signature (see [`super::key_signature`]) is not reported as an unknown function.
+pub fn find_unknown_names(definition: &KeyDefinition) -> Vec<UnknownName> {
+    let mut finder = UnknownNameFinder::default();
+    finder.visit_key_definition(definition);
+    finder.unknowns
+}
+
+#[derive(Default)]
+struct UnknownNameFinder {
+    unknowns: Vec<UnknownName>,
+}
+
+impl UnknownNameFinder {
+    fn visit_call(&mut self, function_name: &str, args: &[KeyExpression]) {
+        let signature = key_function_signature(function_name);
+        if signature.is_none() {
+            self.unknowns.push(UnknownName {
+                kind: UnknownNameKind::Function,
+                name: function_name.to_string(),
+                suggestions: suggest_key_function_names(function_name),
+            });
+        }
+        for (position, arg) in args.iter().enumerate() {
+            let is_group_name = signature
+                .and_then(|signature| signature.arg_kind(position))
+                .is_some_and(|kind| kind == ArgumentKind::GroupName);
+            if !is_group_name {
+                self.visit_key_expression(arg);
+            }
+        }
+    }
+}
+
+impl Visit for UnknownNameFinder {
+    // `country:`'s argument is visited as a key name, too
+    fn visit_key_name(&mut self, key_name: &KeyName) {
+        if let KeyName::Unknown(ref name) = key_name {
+            self.unknowns.push(unknown_key(name));
+        }
+    }
+
+    fn visit_custom_key_function(&mut self, custom: &CustomKeyFunction) {
+        self.visit_call(&custom.function_name, &custom.args);
+    }
+
+    fn visit_unknown_key_function(&mut self, unknown: &UnknownKeyFunction) {
+        self.visit_call(&unknown.function_name, &unknown.args);
+    }
+}
+
+fn unknown_key(name: &str) -> UnknownName {
+    UnknownName {
+        kind: UnknownNameKind::Key,
+        name: name.to_string(),
+        suggestions: suggest_key_names(name),
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::key_parser::parse_key_definition;
+
+    #[rstest::rstest]
+    #[case("ipsource", "ipsource", 0)]
+    #[case("ip6sorce", "ip6source", 1)]
+    #[case("ipsoucre", "ipsource", 1)]
+    #[case("grup", "group", 1)]
+    #[case("", "group", 5)]
+    #[case("country", "group", 6)]
+    fn test_edit_distance(#[case] a: &str, #[case] b: &str, #[case] expected: usize) {
+        assert_eq!(edit_distance(a, b), expected);
+        assert_eq!(edit_distance(b, a), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("ip6sorce", &["ip6source"])]
+    #[case("IPSOURCE", &["ipsource"])]
+    #[case("ip6fragofset", &["ip6fragoffset"])]
+    #[case("ip6tcs", &["ip6tos"])]
+    #[case("zzzzzzzz", &[])]
+    fn test_suggest_key_names(#[case] name: &str, #[case] expected: &[&str]) {
+        assert_eq!(suggest_key_names(name), expected);
+    }
+
+    #[test]
+    fn test_find_unknown_names() {
+        let (_, definition) =
+            parse_key_definition("ip6sorce,grup:ipsource:trusted,group:ipdestinaton:a,ipsource")
+                .expect("should parse");
+        let unknowns = find_unknown_names(&definition);
+        let messages: Vec<String> = unknowns.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown key `ip6sorce`; did you mean `ip6source`?",
+                "unknown key function `grup`; did you mean `group`?",
+                "unknown key `trusted`",
+                "unknown key `ipdestinaton`; did you mean `ipdestination`?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_unknown_names_skips_group_names_of_mismatched_calls() {
+        // `group:` with a nested function as a group name doesn't match the signature
+        // of `group:`, so it parses as an unknown function call named "group"
+        let (_, definition) =
+            parse_key_definition("group:ipsorce:[country:ipsource]:trusted").expect("should parse");
+        assert_eq!(
+            find_unknown_names(&definition),
+            vec![UnknownName {
+                kind: UnknownNameKind::Key,
+                name: "ipsorce".to_string(),
+                suggestions: vec!["ipsource"],
+            }]
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Types of keys and key expressions, and a type checker for key definitions.
+//!
+//! Every known key has a [`KeyType`], the type of its values, and every known key
+//! function produces values of its signature's
+//! [`result`](super::key_signature::KeyFunctionSignature::result) type. A signature may
+//! also constrain the types of its key arguments (its
+//! [`arg_types`](super::key_signature::KeyFunctionSignature::arg_types)); e.g.
+//! `country:` looks up the country an IP address is in, so `country:ip6ttl` is a type
+//! error. [`check_key_definition_types`] finds such errors, with the span of each
+//! offending argument:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{
+//!     key_parser::parse_key_definition_spanned, key_type::check_key_definition_types,
+//! };
+//!
+//! let input = "ipsource,country:ip6ttl";
+//! let definition = parse_key_definition_spanned(input).unwrap();
+//! let errors = check_key_definition_types(&definition);
+//! assert_eq!(
+//!     errors[0].to_string(),
+//!     "country expects an IP address as argument 1, found an integer"
+//! );
+//! assert_eq!(errors[0].span.slice(input), "ip6ttl");
+//! ```
+//!
+//! Unknown keys and calls to unknown key functions have no type, and aren't checked;
+//! see [`super::key_suggest`] and [`super::key_signature`] for those.
+
+use std::fmt;
+
+use super::key_signature::{
+    key_function_signature, ArgumentKind, KeyType, TypeConstraint, KEY_FUNCTION_NAME_COUNTRY,
+    KEY_FUNCTION_NAME_GROUP, KEY_FUNCTION_NAME_OR,
+};
+use super::key_span::{
+    KeyFunctionKind, Span, SpannedKeyDefinition, SpannedKeyExpression, SpannedKeyFunction,
+};
+use super::{KeyExpression, KeyFunction, KeyName};
+
+impl KeyName {
+    /// The type of this key's values, or `None` for an unknown key.
+    pub fn key_type(&self) -> Option<KeyType> {
+        let key_type = match self {
+            KeyName::IpSource | KeyName::IpDestination => KeyType::Ipv4Address,
+            KeyName::Ip6Source | KeyName::Ip6Destination => KeyType::Ipv6Address,
+            KeyName::Ip6Offset
+            | KeyName::Ip6DSCP
+            | KeyName::Ip6FlowLabel
+            | KeyName::Ip6TTL
+            | KeyName::Ip6Bytes
+            | KeyName::Ip6FragmentOffset
+            | KeyName::Ip6NextHeader => KeyType::Integer,
+            KeyName::Ip6TOS | KeyName::Ip6ECN => KeyType::Bits,
+            KeyName::Ip6DSCPName => KeyType::String,
+            KeyName::Ip6Extensions => KeyType::List,
+            KeyName::Ip6FragmentMFlag => KeyType::Boolean,
+            KeyName::Unknown(_) => return None,
+        };
+        Some(key_type)
+    }
+}
+
+impl KeyFunction {
+    /// The type of the values this call produces, or `None` for a call to an unknown
+    /// key function. An `or:` call produces a value of one of its keys, so its type is
+    /// theirs if they all have the same one, and `None` otherwise.
+    pub fn result_type(&self) -> Option<KeyType> {
+        let function_name = match self {
+            KeyFunction::Group(_) => KEY_FUNCTION_NAME_GROUP,
+            KeyFunction::Country(_) => KEY_FUNCTION_NAME_COUNTRY,
+            KeyFunction::Custom(custom) if custom.function_name == KEY_FUNCTION_NAME_OR => {
+                return common_key_type(custom.args.iter().map(KeyExpression::key_type));
+            }
+            KeyFunction::Custom(custom) => &custom.function_name,
+            KeyFunction::Unknown(_) => return None,
+        };
+        key_function_signature(function_name).map(|signature| signature.result)
+    }
+}
+
+impl KeyExpression {
+    /// The type of this key expression's values, or `None` if it is unknown or a
+    /// literal.
+    pub fn key_type(&self) -> Option<KeyType> {
+        match self {
+            KeyExpression::KeyName(key_name) => key_name.key_type(),
+            KeyExpression::KeyFunction(function) => function.result_type(),
+            KeyExpression::Literal(_) => None,
+        }
+    }
+}
+
+impl SpannedKeyExpression {
+    /// The type of this key expression's values; see [`KeyExpression::key_type`].
+    pub fn key_type(&self) -> Option<KeyType> {
+        match self {
+            SpannedKeyExpression::KeyName { key_name, .. } => key_name.key_type(),
+            SpannedKeyExpression::KeyFunction(function) => function.result_type(),
+            SpannedKeyExpression::Literal { .. } => None,
+        }
+    }
+}
+
+impl SpannedKeyFunction {
+    /// The type of the values this call produces; see [`KeyFunction::result_type`].
+    pub fn result_type(&self) -> Option<KeyType> {
+        if self.kind == KeyFunctionKind::Unknown {
+            return None;
+        }
+        if self.function_name == KEY_FUNCTION_NAME_OR {
+            return common_key_type(self.args.iter().map(SpannedKeyExpression::key_type));
+        }
+        key_function_signature(&self.function_name).map(|signature| signature.result)
+    }
+}
+
+/// The type all of `types` are, if they're all known and the same.
+fn common_key_type(mut types: impl Iterator<Item = Option<KeyType>>) -> Option<KeyType> {
+    let first = types.next()??;
+    types
+        .all(|key_type| key_type == Some(first))
+        .then_some(first)
+}
+
+/// A key argument to a key function whose type the function doesn't accept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeError {
+    /// The span of the offending argument.
+    pub span: Span,
+    pub function: &'static str,
+    /// The (zero-based) position of the offending argument.
+    pub position: usize,
+    pub expected: TypeConstraint,
+    pub found: KeyType,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} expects {} as argument {}, found {}",
+            self.function,
+            self.expected.description_with_article(),
+            self.position + 1,
+            self.found.description_with_article()
+        )
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Check the type of every key argument to a known key function in a key definition.
+/// Errors are in the order their arguments appear in the input.
+pub fn check_key_definition_types(definition: &SpannedKeyDefinition) -> Vec<TypeError> {
+    let mut errors = Vec::new();
+    for key in &definition.keys {
+        check_key_expression_types(key, &mut errors);
+    }
+    errors
+}
+
+fn check_key_expression_types(expression: &SpannedKeyExpression, errors: &mut Vec<TypeError>) {
+    let SpannedKeyExpression::KeyFunction(function) = expression else {
+        return;
+    };
+    // a call that the parser couldn't type doesn't match its function's signature, if
+    // it has one, so its arguments can't be matched up with the signature's types
+    let signature = match function.kind {
+        KeyFunctionKind::Unknown => None,
+        _ => key_function_signature(&function.function_name),
+    };
+    for (position, arg) in function.args.iter().enumerate() {
+        if let Some(signature) = &signature {
+            let is_key = matches!(
+                signature.arg_kind(position),
+                Some(ArgumentKind::KeyExpression | ArgumentKind::KeyName)
+            );
+            let expected = signature.arg_type(position);
+            match arg.key_type() {
+                Some(found) if is_key && !expected.accepts(found) => errors.push(TypeError {
+                    span: arg.span(),
+                    function: signature.name,
+                    position,
+                    expected,
+                    found,
+                }),
+                _ => {}
+            }
+        }
+        check_key_expression_types(arg, errors);
+    }
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{
+        key_literal::LiteralKind,
+        key_parser::parse_key_definition_spanned,
+        key_registry::{register_key_function, unregister_key_function, KeyFunctionPlugin},
+        key_signature::KeyFunctionSignature,
+    };
+
+    /// `type_check_mask:key:bits`; a registered function with the same signature as
+    /// `mask:`. It has a name of its own, since the registry is shared by all tests.
+    struct MaskKeyFunction;
+
+    impl KeyFunctionPlugin for MaskKeyFunction {
+        fn signature(&self) -> KeyFunctionSignature {
+            KeyFunctionSignature {
+                name: "type_check_mask",
+                args: &[
+                    ArgumentKind::KeyExpression,
+                    ArgumentKind::Literal(LiteralKind::Integer),
+                ],
+                min_arity: 2,
+                max_arity: Some(2),
+                arg_types: &[TypeConstraint::Address],
+                result: KeyType::String,
+            }
+        }
+    }
+
+    fn check(input: &str) -> Vec<(String, &str)> {
+        let definition = parse_key_definition_spanned(input).expect("should parse");
+        check_key_definition_types(&definition)
+            .iter()
+            .map(|error| (error.to_string(), error.span.slice(input)))
+            .collect()
+    }
+
+    #[test]
+    fn test_key_types() {
+        assert_eq!(KeyName::Ip6Source.key_type(), Some(KeyType::Ipv6Address));
+        assert_eq!(KeyName::Ip6FragmentMFlag.key_type(), Some(KeyType::Boolean));
+        assert_eq!(KeyName::Unknown("x".to_string()).key_type(), None);
+        let definition: crate::key::KeyDefinition = "group:ipsource:a,country:ip6source,f:ipsource"
+            .parse()
+            .expect("should parse");
+        let types: Vec<_> = definition.iter().map(KeyExpression::key_type).collect();
+        assert_eq!(
+            types,
+            vec![Some(KeyType::String), Some(KeyType::String), None]
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("ipsource,country:ipsource,country:ip6destination", &[])]
+    #[case("group:ip6ttl:a:b,group:[country:ip6source]:a", &[])]
+    #[case(
+        "ipsource,country:ip6ttl",
+        &[("country expects an IP address as argument 1, found an integer", "ip6ttl")]
+    )]
+    #[case(
+        "group:[country:ip6fragm]:a,f:[country:ip6extensions]",
+        &[
+            ("country expects an IP address as argument 1, found a boolean", "ip6fragm"),
+            ("country expects an IP address as argument 1, found a list", "ip6extensions"),
+        ]
+    )]
+    #[case(
+        "mask:ip6ttl:24,mask:ip6source:64,mask:[or:ipsource:ipdestination]:24",
+        &[("mask expects an IP address as argument 1, found an integer", "ip6ttl")]
+    )]
+    #[case(
+        "mask:[or:ip6ttl:ip6dscp]:24,mask:[or:ipsource:ip6ttl]:24",
+        &[("mask expects an IP address as argument 1, found an integer", "or:ip6ttl:ip6dscp")]
+    )]
+    #[case(
+        "mask:[group:ipsource:a]:24",
+        &[("mask expects an IP address as argument 1, found a string", "group:ipsource:a")]
+    )]
+    // unknown keys and calls aren't typed
+    #[case("country:mykey,country:ipsource:ip6source,f:ip6ttl", &[])]
+    fn test_check_key_definition_types(#[case] input: &str, #[case] expected: &[(&str, &str)]) {
+        let expected: Vec<(String, &str)> = expected
+            .iter()
+            .map(|(message, span)| (message.to_string(), *span))
+            .collect();
+        assert_eq!(check(input), expected);
+    }
+
+    #[test]
+    fn test_check_registered_key_function_types() {
+        register_key_function(MaskKeyFunction).expect("should register");
+        let errors = check(
+            "type_check_mask:ip6source:64,type_check_mask:ip6dscp:8,\
+             type_check_mask:[group:ipsource:a]:8",
+        );
+        assert!(unregister_key_function("type_check_mask"));
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "type_check_mask expects an IP address as argument 1, found an integer"
+                        .to_string(),
+                    "ip6dscp"
+                ),
+                (
+                    "type_check_mask expects an IP address as argument 1, found a string"
+                        .to_string(),
+                    "group:ipsource:a"
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Traversal of key definitions: [`Visit`] walks a key definition by reference, and
+//! [`Fold`] rebuilds one by value, so that any node can be replaced.
+//!
+//! Each trait method has a default implementation that recurses into the node's
+//! children by calling the free function of the same name; an implementation that
+//! overrides a method can call that function to keep recursing. For example, to
+//! collect every key a definition refers to:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{key_visit::Visit, KeyDefinition, KeyName};
+//!
+//! struct Keys(Vec<KeyName>);
+//!
+//! impl Visit for Keys {
+//!     fn visit_key_name(&mut self, key_name: &KeyName) {
+//!         self.0.push(key_name.clone());
+//!     }
+//! }
+//!
+//! let definition: KeyDefinition = "ipsource,group:[country:ip6source]:a".parse().unwrap();
+//! let mut keys = Keys(Vec::new());
+//! keys.visit_key_definition(&definition);
+//! assert_eq!(keys.0, vec![KeyName::IpSource, KeyName::Ip6Source]);
+//! ```
+//!
+//! Or to replace `ipsource` with `or:ipsource:ip6source` wherever it appears:
+//!
+//! ```
+//! use sflowrt_rs_flow::key::{
+//!     key_function::UnknownKeyFunction, key_visit::{self, Fold}, KeyDefinition,
+//!     KeyExpression, KeyFunction, KeyName,
+//! };
+//!
+//! struct EitherSource;
+//!
+//! impl Fold for EitherSource {
+//!     fn fold_key_expression(&mut self, expression: KeyExpression) -> KeyExpression {
+//!         match expression {
+//!             KeyExpression::KeyName(KeyName::IpSource) => KeyFunction::Unknown(UnknownKeyFunction {
+//!                 function_name: "or".to_string(),
+//!                 args: vec![KeyName::IpSource.into(), KeyName::Ip6Source.into()],
+//!             })
+//!             .into(),
+//!             expression => key_visit::fold_key_expression(self, expression),
+//!         }
+//!     }
+//! }
+//!
+//! let definition: KeyDefinition = "ipsource,group:ipsource:a".parse().unwrap();
+//! let definition = EitherSource.fold_key_definition(definition);
+//! assert_eq!(
+//!     definition.to_string(),
+//!     "or:ipsource:ip6source,group:[or:ipsource:ip6source]:a"
+//! );
+//! ```
+//!
+//! `country:`'s argument is visited (and folded) as a key name; folding it into
+//! another kind of expression isn't possible.
+
+use super::key_function::{
+    CountryKeyFunction, CustomKeyFunction, GroupKeyFunction, UnknownKeyFunction,
+};
+use super::key_literal::Literal;
+use super::{KeyDefinition, KeyExpression, KeyFunction, KeyName};
+
+/// A traversal of a key definition by reference; see the [module docs](self).
+pub trait Visit {
+    fn visit_key_definition(&mut self, definition: &KeyDefinition) {
+        visit_key_definition(self, definition);
+    }
+
+    fn visit_key_expression(&mut self, expression: &KeyExpression) {
+        visit_key_expression(self, expression);
+    }
+
+    fn visit_key_name(&mut self, _key_name: &KeyName) {}
+
+    fn visit_literal(&mut self, _literal: &Literal) {}
+
+    fn visit_key_function(&mut self, function: &KeyFunction) {
+        visit_key_function(self, function);
+    }
+
+    fn visit_group_key_function(&mut self, group: &GroupKeyFunction) {
+        visit_group_key_function(self, group);
+    }
+
+    fn visit_group_name(&mut self, _group_name: &str) {}
+
+    fn visit_country_key_function(&mut self, country: &CountryKeyFunction) {
+        visit_country_key_function(self, country);
+    }
+
+    fn visit_custom_key_function(&mut self, custom: &CustomKeyFunction) {
+        visit_custom_key_function(self, custom);
+    }
+
+    fn visit_unknown_key_function(&mut self, unknown: &UnknownKeyFunction) {
+        visit_unknown_key_function(self, unknown);
+    }
+}
+
+pub fn visit_key_definition<V: Visit + ?Sized>(visitor: &mut V, definition: &KeyDefinition) {
+    for key in &definition.keys {
+        visitor.visit_key_expression(key);
+    }
+}
+
+pub fn visit_key_expression<V: Visit + ?Sized>(visitor: &mut V, expression: &KeyExpression) {
+    match expression {
+        KeyExpression::KeyName(key_name) => visitor.visit_key_name(key_name),
+        KeyExpression::KeyFunction(function) => visitor.visit_key_function(function),
+        KeyExpression::Literal(literal) => visitor.visit_literal(literal),
+    }
+}
+
+pub fn visit_key_function<V: Visit + ?Sized>(visitor: &mut V, function: &KeyFunction) {
+    match function {
+        KeyFunction::Group(group) => visitor.visit_group_key_function(group),
+        KeyFunction::Country(country) => visitor.visit_country_key_function(country),
+        KeyFunction::Custom(custom) => visitor.visit_custom_key_function(custom),
+        KeyFunction::Unknown(unknown) => visitor.visit_unknown_key_function(unknown),
+    }
+}
+
+pub fn visit_group_key_function<V: Visit + ?Sized>(visitor: &mut V, group: &GroupKeyFunction) {
+    visitor.visit_key_expression(&group.key);
+    for group_name in &group.group_names {
+        visitor.visit_group_name(group_name);
+    }
+}
+
+pub fn visit_country_key_function<V: Visit + ?Sized>(
+    visitor: &mut V,
+    country: &CountryKeyFunction,
+) {
+    visitor.visit_key_name(&KeyName::from(country.arg.as_str()));
+}
+
+pub fn visit_custom_key_function<V: Visit + ?Sized>(visitor: &mut V, custom: &CustomKeyFunction) {
+    for arg in &custom.args {
+        visitor.visit_key_expression(arg);
+    }
+}
+
+pub fn visit_unknown_key_function<V: Visit + ?Sized>(
+    visitor: &mut V,
+    unknown: &UnknownKeyFunction,
+) {
+    for arg in &unknown.args {
+        visitor.visit_key_expression(arg);
+    }
+}
+
+/// A rewrite of a key definition, by value; see the [module docs](self).
+pub trait Fold {
+    fn fold_key_definition(&mut self, definition: KeyDefinition) -> KeyDefinition {
+        fold_key_definition(self, definition)
+    }
+
+    fn fold_key_expression(&mut self, expression: KeyExpression) -> KeyExpression {
+        fold_key_expression(self, expression)
+    }
+
+    fn fold_key_name(&mut self, key_name: KeyName) -> KeyName {
+        key_name
+    }
+
+    fn fold_literal(&mut self, literal: Literal) -> Literal {
+        literal
+    }
+
+    fn fold_key_function(&mut self, function: KeyFunction) -> KeyFunction {
+        fold_key_function(self, function)
+    }
+
+    fn fold_group_key_function(&mut self, group: GroupKeyFunction) -> GroupKeyFunction {
+        fold_group_key_function(self, group)
+    }
+
+    fn fold_group_name(&mut self, group_name: String) -> String {
+        group_name
+    }
+
+    fn fold_country_key_function(&mut self, country: CountryKeyFunction) -> CountryKeyFunction {
+        fold_country_key_function(self, country)
+    }
+
+    fn fold_custom_key_function(&mut self, custom: CustomKeyFunction) -> CustomKeyFunction {
+        fold_custom_key_function(self, custom)
+    }
+
+    fn fold_unknown_key_function(&mut self, unknown: UnknownKeyFunction) -> UnknownKeyFunction {
+        fold_unknown_key_function(self, unknown)
+    }
+}
+
+pub fn fold_key_definition<F: Fold + ?Sized>(
+    folder: &mut F,
+    definition: KeyDefinition,
+) -> KeyDefinition {
+    KeyDefinition {
+        keys: definition
+            .keys
+            .into_iter()
+            .map(|key| folder.fold_key_expression(key))
+            .collect(),
+    }
+}
+
+pub fn fold_key_expression<F: Fold + ?Sized>(
+    folder: &mut F,
+    expression: KeyExpression,
+) -> KeyExpression {
+    match expression {
+        KeyExpression::KeyName(key_name) => folder.fold_key_name(key_name).into(),
+        KeyExpression::KeyFunction(function) => folder.fold_key_function(function).into(),
+        KeyExpression::Literal(literal) => folder.fold_literal(literal).into(),
+    }
+}
+
+pub fn fold_key_function<F: Fold + ?Sized>(folder: &mut F, function: KeyFunction) -> KeyFunction {
+    match function {
+        KeyFunction::Group(group) => folder.fold_group_key_function(group).into(),
+        KeyFunction::Country(country) => folder.fold_country_key_function(country).into(),
+        KeyFunction::Custom(custom) => folder.fold_custom_key_function(custom).into(),
+        KeyFunction::Unknown(unknown) => folder.fold_unknown_key_function(unknown).into(),
+    }
+}
+
+pub fn fold_group_key_function<F: Fold + ?Sized>(
+    folder: &mut F,
+    group: GroupKeyFunction,
+) -> GroupKeyFunction {
+    GroupKeyFunction {
+        key: Box::new(folder.fold_key_expression(*group.key)),
+        group_names: group
+            .group_names
+            .into_iter()
+            .map(|group_name| folder.fold_group_name(group_name))
+            .collect(),
+    }
+}
+
+pub fn fold_country_key_function<F: Fold + ?Sized>(
+    folder: &mut F,
+    country: CountryKeyFunction,
+) -> CountryKeyFunction {
+    let key_name = folder.fold_key_name(KeyName::from(country.arg.as_str()));
+    CountryKeyFunction {
+        arg: key_name.as_identifier().to_string(),
+    }
+}
+
+pub fn fold_custom_key_function<F: Fold + ?Sized>(
+    folder: &mut F,
+    custom: CustomKeyFunction,
+) -> CustomKeyFunction {
+    CustomKeyFunction {
+        function_name: custom.function_name,
+        args: fold_args(folder, custom.args),
+    }
+}
+
+pub fn fold_unknown_key_function<F: Fold + ?Sized>(
+    folder: &mut F,
+    unknown: UnknownKeyFunction,
+) -> UnknownKeyFunction {
+    UnknownKeyFunction {
+        function_name: unknown.function_name,
+        args: fold_args(folder, unknown.args),
+    }
+}
+
+fn fold_args<F: Fold + ?Sized>(folder: &mut F, args: Vec<KeyExpression>) -> Vec<KeyExpression> {
+    args.into_iter()
+        .map(|arg| folder.fold_key_expression(arg))
+        .collect()
+}
+
+// tests //////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records every leaf it visits, in order.
+    #[derive(Default)]
+    struct Leaves(Vec<String>);
+
+    impl Visit for Leaves {
+        fn visit_key_name(&mut self, key_name: &KeyName) {
+            self.0.push(format!("key {key_name}"));
+        }
+
+        fn visit_literal(&mut self, literal: &Literal) {
+            self.0.push(format!("literal {literal}"));
+        }
+
+        fn visit_group_name(&mut self, group_name: &str) {
+            self.0.push(format!("group {group_name}"));
+        }
+    }
+
+    #[test]
+    fn test_visit_order() {
+        let definition: KeyDefinition = "ipsource,f:[group:[country:ip6source]:a:b]:24,x"
+            .parse()
+            .expect("should parse");
+        let mut leaves = Leaves::default();
+        leaves.visit_key_definition(&definition);
+        assert_eq!(
+            leaves.0,
+            vec![
+                "key ipsource",
+                "key ip6source",
+                "group a",
+                "group b",
+                "literal 24",
+                "key x",
+            ]
+        );
+    }
+
+    /// Renames unknown keys and group names to upper case.
+    struct Shout;
+
+    impl Fold for Shout {
+        fn fold_key_name(&mut self, key_name: KeyName) -> KeyName {
+            match key_name {
+                KeyName::Unknown(name) => KeyName::Unknown(name.to_uppercase()),
+                key_name => key_name,
+            }
+        }
+
+        fn fold_group_name(&mut self, group_name: String) -> String {
+            group_name.to_uppercase()
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        let definition: KeyDefinition = "ipsource,country:mykey,f:[group:other:a]:\"s\""
+            .parse()
+            .expect("should parse");
+        assert_eq!(
+            Shout.fold_key_definition(definition).to_string(),
+            "ipsource,country:MYKEY,f:[group:OTHER:A]:\"s\""
+        );
+    }
+}
@@ -1,23 +1,51 @@
-/// sFlow-RT Flow key structures.
-/// See: https://sflow-rt.com/define_flow.php
+//! sFlow-RT Flow key structures.
+//! See: <https://sflow-rt.com/define_flow.php>
+
+pub mod key_borrowed;
+pub mod key_builder;
+pub mod key_cost;
+pub mod key_diff;
+pub mod key_error;
+pub mod key_fingerprint;
 pub mod key_function;
+pub mod key_layer;
+pub mod key_lexer;
+pub mod key_lint;
+pub mod key_literal;
+pub mod key_normalize;
 pub mod key_parser;
+pub mod key_registry;
+#[cfg(feature = "serde")]
+pub mod key_serde;
+pub mod key_signature;
+pub mod key_span;
+pub mod key_suggest;
+pub mod key_type;
+pub mod key_visit;
 
-use std::{collections::HashMap, sync::LazyLock};
+use std::{collections::HashMap, fmt, str::FromStr, sync::LazyLock};
 
 use fnv::FnvBuildHasher;
 use key_function::*;
+use key_literal::Literal;
 #[cfg(test)]
 use strum::EnumCount;
 
 /// A key expression. Flows are defined from (and bucketed based on) a vector of key
-/// expressions. Contains either a plain key name, or a key value function expression.
+/// expressions. Contains either a plain key name, a key value function expression, or
+/// (as a key function argument only) a literal value.
 ///
 /// See [sFlow-RT's documentation on Defining Flows](https://sflow-rt.com/define_flow.php).
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum KeyExpression {
     KeyName(KeyName),
     KeyFunction(KeyFunction),
+    Literal(Literal),
 }
 
 impl From<KeyName> for KeyExpression {
@@ -32,12 +60,30 @@ impl From<KeyFunction> for KeyExpression {
     }
 }
 
+impl From<Literal> for KeyExpression {
+    fn from(value: Literal) -> Self {
+        Self::Literal(value)
+    }
+}
+
+/// Writes the key expression in canonical sFlow-RT DSL. A key function is written
+/// without brackets; they are only needed (and written) around key function arguments.
+impl fmt::Display for KeyExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyExpression::KeyName(key_name) => write!(f, "{key_name}"),
+            KeyExpression::KeyFunction(function) => write!(f, "{function}"),
+            KeyExpression::Literal(literal) => write!(f, "{literal}"),
+        }
+    }
+}
+
 /// A flow key. This is an aspect of the network-level information we can capture,
 /// modify with key value functions, and then categorize flows by.
 ///
 /// See [sFlow-RT's documentation on Flow Keys](https://sflow-rt.com/define_flow.php#keys).
 #[cfg_attr(test, derive(EnumCount))]
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum KeyName {
     IpSource,
     IpDestination,
@@ -94,6 +140,28 @@ impl KeyName {
     pub fn from_sflowrt_key_name(key_name: &str) -> Option<Self> {
         KEY_NAME_TO_VARIANT.get(key_name).map(|k| (*k).clone())
     }
+
+    /// The key name as it was (or would be) written in the DSL. Unlike
+    /// [`KeyName::to_sflowrt_key_name`], this also gives the text of unknown key names.
+    pub(crate) fn as_identifier(&self) -> &str {
+        match self {
+            KeyName::Unknown(ref name) => name,
+            _ => self.to_sflowrt_key_name().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for KeyName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_identifier())
+    }
+}
+
+/// The known key name `key_name`, or else an unknown one.
+impl From<&str> for KeyName {
+    fn from(key_name: &str) -> Self {
+        Self::from_sflowrt_key_name(key_name).unwrap_or_else(|| Self::Unknown(key_name.to_string()))
+    }
 }
 
 /// A hashmap from the sFlow-RT key name as a string to the key name enum value.
@@ -141,20 +209,127 @@ static KEY_VARIANT_TO_NAME: LazyLock<HashMap<KeyName, &'static str, FnvBuildHash
 /// arguments) to modify the key instance of a flow before it is categorized.
 ///
 /// See [sFlow-RT's documentation on Key Functions](https://sflow-rt.com/define_flow.php#keyfunctions).
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum KeyFunction {
     Group(GroupKeyFunction),
     Country(CountryKeyFunction),
     // Add more known key value functions here
+    /// A call to a known key function without a variant of its own: a built-in one
+    /// that is only described by its [signature](key_signature), such as `mask:`, or
+    /// a user-defined one, registered via [`key_registry::register_key_function`].
+    Custom(CustomKeyFunction),
     /// An unknown key function.
     Unknown(UnknownKeyFunction),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl fmt::Display for KeyFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFunction::Group(group) => write!(f, "{group}"),
+            KeyFunction::Country(country) => write!(f, "{country}"),
+            KeyFunction::Custom(custom) => write!(f, "{custom}"),
+            KeyFunction::Unknown(unknown) => write!(f, "{unknown}"),
+        }
+    }
+}
+
+/// A flow key definition: the key expressions that flows are bucketed by, in order.
+///
+/// Obtain one by parsing DSL text (see [`key_parser`], or [`str::parse`]), or build
+/// one with [`KeyDefinition::builder`].
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct KeyDefinition {
     keys: Vec<KeyExpression>,
 }
 
+impl KeyDefinition {
+    pub fn new(keys: Vec<KeyExpression>) -> Self {
+        Self { keys }
+    }
+
+    pub fn builder() -> key_builder::KeyDefinitionBuilder {
+        key_builder::KeyDefinitionBuilder::default()
+    }
+
+    pub fn keys(&self) -> &[KeyExpression] {
+        &self.keys
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, KeyExpression> {
+        self.keys.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether there are no key expressions. An empty key definition can't be written
+    /// in the DSL.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn push(&mut self, key: impl Into<KeyExpression>) {
+        self.keys.push(key.into());
+    }
+
+    pub fn into_keys(self) -> Vec<KeyExpression> {
+        self.keys
+    }
+}
+
+impl IntoIterator for KeyDefinition {
+    type Item = KeyExpression;
+    type IntoIter = std::vec::IntoIter<KeyExpression>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.keys.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a KeyDefinition {
+    type Item = &'a KeyExpression;
+    type IntoIter = std::slice::Iter<'a, KeyExpression>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.keys.iter()
+    }
+}
+
+impl FromIterator<KeyExpression> for KeyDefinition {
+    fn from_iter<I: IntoIterator<Item = KeyExpression>>(iter: I) -> Self {
+        Self {
+            keys: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Writes the key definition in canonical sFlow-RT DSL, such that parsing it again
+/// (e.g. with [`key_parser::parse_key_definition_complete`]) gives back an equal key
+/// definition; this holds for every key definition the parser can produce.
+impl fmt::Display for KeyDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, key) in self.keys.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{key}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a complete key definition; see
+/// [`key_parser::parse_key_definition_complete`].
+impl FromStr for KeyDefinition {
+    type Err = key_error::KeySyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        key_parser::parse_key_definition_complete(s)
+    }
+}
+
 // tests //////////////////////////////////////////////////////////////////////////////
 #[cfg(test)]
 mod tests {
@@ -194,9 +369,9 @@ mod tests {
     fn test_key_name_mappings() {
         // testing rule (1) ///////////////////////////////////////////////////////////
         // ensure there are no `KeyName::Unknown` variants in `KEY_NAME_TO_VARIANT`.
-        fn _check_not_unknown_variant(key_name: &KeyName) -> anyhow::Result<()> {
+        fn _check_not_unknown_variant(key_name: &KeyName) -> Result<(), String> {
             match key_name {
-                KeyName::Unknown(ref kn) => Err(anyhow::anyhow!(
+                KeyName::Unknown(ref kn) => Err(format!(
                     "Found `KeyName::Unknown` variant with key name `{kn}`"
                 )),
                 _ => Ok(()),
@@ -251,4 +426,127 @@ mod tests {
         assert_eq!(n2v_len, n_non_unknown_variants, "mapping `KEY_NAME_TO_VARIANT`'s length ({n2v_len}) does not match the number of non-`Unknown` variants of `KeyName` ({n_non_unknown_variants})");
         assert_eq!(v2n_len, n_non_unknown_variants, "mapping `KEY_VARIANT_TO_NAME`'s length ({v2n_len}) does not match the number of non-`Unknown` variants of `KeyName` ({n_non_unknown_variants})");
     }
+
+    mod display {
+        use std::net::IpAddr;
+
+        use proptest::prelude::*;
+
+        use crate::key::key_function::{CountryKeyFunction, GroupKeyFunction, UnknownKeyFunction};
+        use crate::key::key_literal::{AddressLiteral, Literal};
+        use crate::key::key_parser::{
+            parse_key_definition_borrowed, parse_key_definition_complete,
+        };
+        use crate::key::key_signature::KEY_FUNCTION_SIGNATURES;
+        use crate::key::{KeyDefinition, KeyExpression, KeyFunction, KeyName, KEY_NAME_TO_VARIANT};
+
+        #[rstest::rstest]
+        #[case("ipsource,ip6_offset")]
+        #[case("group:[country:ip6source]:trusted:\"a,b\":\"24\"")]
+        #[case("f:ipsource:-24:{10.0.0.0/8}:{fe80::/10}:/a\\/b/:\"say \\\"hi\\\"\"")]
+        fn test_display_is_canonical(#[case] input: &str) {
+            let definition = parse_key_definition_complete(input).expect("should parse");
+            assert_eq!(definition.to_string(), input);
+        }
+
+        #[test]
+        fn test_display_canonicalizes() {
+            let definition =
+                parse_key_definition_complete("group:ipsource:\"trusted\",f:10.0.0.0/8")
+                    .expect("should parse");
+            assert_eq!(
+                definition.to_string(),
+                "group:ipsource:trusted,f:{10.0.0.0/8}"
+            );
+        }
+
+        fn arb_key_name() -> impl Strategy<Value = KeyName> {
+            let known: Vec<KeyName> = KEY_NAME_TO_VARIANT.values().cloned().collect();
+            prop_oneof![
+                proptest::sample::select(known),
+                "[a-z][a-z0-9_]{0,10}"
+                    .prop_filter("known key name", |name| {
+                        !KEY_NAME_TO_VARIANT.contains_key(name.as_str())
+                    })
+                    .prop_map(KeyName::Unknown),
+            ]
+        }
+
+        fn arb_literal() -> impl Strategy<Value = Literal> {
+            prop_oneof![
+                any::<i64>().prop_map(Literal::Integer),
+                "\\PC{0,10}".prop_map(Literal::String),
+                // a trailing `\\` would escape the closing `/`
+                "[a-z0-9/.^$*+?()|,:\\[\\]]{0,10}".prop_map(Literal::Regex),
+                (any::<IpAddr>(), any::<Option<u8>>()).prop_map(|(address, prefix_len)| {
+                    let mut address = AddressLiteral {
+                        address,
+                        prefix_len,
+                    };
+                    address.prefix_len = prefix_len.map(|len| len % (address.max_prefix_len() + 1));
+                    Literal::Address(address)
+                }),
+            ]
+        }
+
+        /// Key expressions that the parser can produce; e.g. no literals (outside of
+        /// key function arguments), and no unknown calls to known key functions.
+        fn arb_key_expression() -> impl Strategy<Value = KeyExpression> {
+            let leaf = arb_key_name().prop_map(KeyExpression::KeyName);
+            leaf.prop_recursive(4, 32, 4, |inner| {
+                let argument = prop_oneof![
+                    inner.clone(),
+                    arb_literal().prop_map(KeyExpression::Literal),
+                ];
+                let unknown_function_name = "[a-z]{1,8}"
+                    .prop_filter("known key function", |name| {
+                        !KEY_FUNCTION_SIGNATURES.contains_key(name.as_str())
+                    });
+                prop_oneof![
+                    arb_key_name().prop_map(KeyExpression::KeyName),
+                    (inner, proptest::collection::vec("\\PC{0,10}", 1..4)).prop_map(
+                        |(key, group_names)| {
+                            KeyFunction::Group(GroupKeyFunction {
+                                key: Box::new(key),
+                                group_names,
+                            })
+                            .into()
+                        }
+                    ),
+                    arb_key_name().prop_map(|key_name| {
+                        KeyFunction::Country(CountryKeyFunction {
+                            arg: key_name.to_string(),
+                        })
+                        .into()
+                    }),
+                    (
+                        unknown_function_name,
+                        proptest::collection::vec(argument, 1..4)
+                    )
+                        .prop_map(|(function_name, args)| {
+                            KeyFunction::Unknown(UnknownKeyFunction {
+                                function_name,
+                                args,
+                            })
+                            .into()
+                        }),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn test_display_round_trips(
+                keys in proptest::collection::vec(arb_key_expression(), 1..4)
+            ) {
+                let definition = KeyDefinition { keys };
+                let printed = definition.to_string();
+                prop_assert_eq!(parse_key_definition_complete(&printed), Ok(definition.clone()), "{}", printed);
+                // the borrowed parser must agree with the owned one
+                let borrowed = parse_key_definition_borrowed(&printed)
+                    .map(crate::key::key_borrowed::BorrowedKeyDefinition::into_owned);
+                prop_assert_eq!(borrowed, Ok(definition), "{}", printed);
+            }
+        }
+    }
 }